@@ -0,0 +1,204 @@
+// bech32 (BIP 173, BIP 350) and CashAddr address codecs, both use the same base32 alphabet
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+// CashAddr version bytes of 160 bit hashes
+pub const CASHADDR_P2PKH: u8 = 0x00;
+pub const CASHADDR_P2SH: u8 = 0x08;
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    data.bytes()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|v| v as u8))
+        .collect()
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    data.iter().map(|v| CHARSET[*v as usize] as char).collect()
+}
+
+// regroups from bits to bits, padding the last group when encoding and rejecting it when decoding
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let max_value = (1 << to) - 1;
+    let max_acc = (1 << (from + to - 1)) - 1;
+    let mut converted = vec![];
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return None;
+        }
+        acc = ((acc << from) | *value as u32) & max_acc;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max_value != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+// addresses are either all lower or all upper case
+fn lowercase(address: &str) -> Option<String> {
+    let lower = address.to_lowercase();
+    (lower == address || address.to_uppercase() == address).then_some(lower)
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+// hrp and output script of a segwit address, witness version 0 is bech32 and later versions bech32m
+pub fn segwit_decode(address: &str) -> Option<(String, Vec<u8>)> {
+    let address = lowercase(address)?;
+    let (hrp, data) = address.rsplit_once('1')?;
+    if hrp.is_empty() || data.len() < 6 || address.len() > 90 {
+        return None;
+    }
+    let data = base32_decode(data)?;
+    let payload = &data[..data.len() - 6];
+    let witness_version = *payload.first()?;
+    let constant = if witness_version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    let mut values = hrp_expand(hrp);
+    values.extend(&data);
+    if bech32_polymod(&values) != constant {
+        return None;
+    }
+    let program = convert_bits(&payload[1..], 5, 8, false)?;
+    let valid = match witness_version {
+        0 => program.len() == 20 || program.len() == 32,
+        1..=16 => (2..=40).contains(&program.len()),
+        _ => false,
+    };
+    if !valid {
+        return None;
+    }
+    // OP_0 or OP_1..OP_16, then the program
+    let mut script = vec![if witness_version == 0 {
+        0
+    } else {
+        0x50 + witness_version
+    }];
+    script.push(program.len() as u8);
+    script.extend(program);
+    Some((hrp.to_string(), script))
+}
+
+fn cashaddr_polymod(values: &[u8]) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0x98f2bc8e61,
+        0x79b76d99e2,
+        0xf33e5fb3c4,
+        0xae2eabe2a8,
+        0x1e4f43e470,
+    ];
+    let mut checksum = 1u64;
+    for value in values {
+        let top = checksum >> 35;
+        checksum = ((checksum & 0x07ffffffff) << 5) ^ *value as u64;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum ^ 1
+}
+
+fn prefix_expand(prefix: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = prefix.bytes().map(|c| c & 31).collect();
+    expanded.push(0);
+    expanded
+}
+
+// prefix, version byte and hash of a CashAddr, the prefix may be left out for default_prefix
+pub fn cashaddr_decode(default_prefix: &str, address: &str) -> Option<(String, u8, Vec<u8>)> {
+    let address = lowercase(address)?;
+    let (prefix, payload) = address
+        .split_once(':')
+        .unwrap_or((default_prefix, &address));
+    let data = base32_decode(payload)?;
+    if data.len() < 8 {
+        return None;
+    }
+    let mut values = prefix_expand(prefix);
+    values.extend(&data);
+    if cashaddr_polymod(&values) != 0 {
+        return None;
+    }
+    let bytes = convert_bits(&data[..data.len() - 8], 5, 8, false)?;
+    let (version, hash) = bytes.split_first()?;
+    Some((prefix.to_string(), *version, hash.to_vec()))
+}
+
+pub fn cashaddr_encode(prefix: &str, version: u8, hash: &[u8]) -> String {
+    let mut payload = vec![version];
+    payload.extend_from_slice(hash);
+    let mut data = convert_bits(&payload, 8, 5, true).unwrap();
+    let mut values = prefix_expand(prefix);
+    values.extend(&data);
+    values.extend([0; 8]);
+    let checksum = cashaddr_polymod(&values);
+    data.extend((0..8).map(|i| ((checksum >> (5 * (7 - i))) & 31) as u8));
+    format!("{}:{}", prefix, base32_encode(&data))
+}
+
+#[test]
+fn test_cashaddr() {
+    // from the CashAddr spec
+    let hash = hex::decode("76a04053bda0a88bda5177b86a15c3b29f559873").unwrap();
+    let address = "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a";
+    assert_eq!(
+        cashaddr_encode("bitcoincash", CASHADDR_P2PKH, &hash),
+        address
+    );
+    let decoded = Some(("bitcoincash".to_string(), CASHADDR_P2PKH, hash.clone()));
+    assert_eq!(cashaddr_decode("bchtest", address), decoded);
+    assert_eq!(
+        cashaddr_decode("bitcoincash", &address[12..].to_uppercase()),
+        decoded
+    );
+    assert_eq!(
+        cashaddr_decode(
+            "bitcoincash",
+            "bitcoincash:ppm2qsznhks23z7629mms6s4cwef74vcwvn0h829pq"
+        ),
+        Some(("bitcoincash".to_string(), CASHADDR_P2SH, hash))
+    );
+    // the prefix is part of the checksum
+    assert!(cashaddr_decode("bchtest", &address[12..]).is_none());
+    assert!(cashaddr_decode(
+        "bitcoincash",
+        "bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6b"
+    )
+    .is_none());
+}
@@ -1,12 +1,12 @@
 use crate::*;
-use base58ck;
 use near_sdk::env::sha256;
 use omni_transaction::bitcoin::bitcoin_transaction::BitcoinTransaction;
+use omni_transaction::bitcoin::encoding::Encodable;
 use omni_transaction::bitcoin::types::{
     Amount, EcdsaSighashType, Hash, LockTime, OutPoint, ScriptBuf, Sequence, TransactionType, TxIn,
     TxOut, Txid, Version, Witness,
 };
-use omni_transaction::bitcoin::utils::{build_script_sig, serialize_ecdsa_signature_from_str};
+use omni_transaction::bitcoin::utils::build_script_sig;
 use omni_transaction::transaction_builder::TransactionBuilder;
 use omni_transaction::transaction_builder::TxBuilder;
 use omni_transaction::types::BITCOIN;
@@ -17,57 +17,166 @@ pub fn sha256d(encoded_tx: Vec<u8>) -> Vec<u8> {
     sha256(&sha256(&encoded_tx))
 }
 
+// a signed tx and its ids, in the byte order shown by explorers
+pub struct SignedTx {
+    pub tx: Vec<u8>,
+    pub txid: String,
+    // only for segwit txs, the txid of a legacy tx already covers the whole tx
+    pub wtxid: Option<String>,
+}
+
+impl SignedTx {
+    pub fn new(tx: &BitcoinTransaction) -> Self {
+        let serialized = tx.serialize();
+        let segwit = tx.input.iter().any(|input| !input.witness.is_empty());
+        SignedTx {
+            txid: reversed_hex(sha256d(encode_without_witness(tx))),
+            wtxid: segwit.then(|| reversed_hex(sha256d(serialized.clone()))),
+            tx: serialized,
+        }
+    }
+}
+
+fn reversed_hex(mut hash: Vec<u8>) -> String {
+    hash.reverse();
+    encode(hash)
+}
+
+// push of a DER signature with its sighash byte and push of an uncompressed key
+const SCRIPT_SIG_MAX_SIZE: usize = 1 + 73 + 1 + 65;
+
+// size of the claim tx once its p2pkh input is signed, at most as the DER length varies
+pub fn max_signed_size(tx: &BitcoinTransaction) -> u128 {
+    // both script_sigs are below 253 bytes, so their length prefix is one byte either way
+    (encode_without_witness(tx).len() - tx.input[0].script_sig.0.len() + SCRIPT_SIG_MAX_SIZE)
+        as u128
+}
+
+// the serialization txids are computed from, without the segwit marker, flag and witnesses
+pub fn encode_without_witness(tx: &BitcoinTransaction) -> Vec<u8> {
+    let mut buffer = vec![];
+    tx.version.encode(&mut buffer).unwrap();
+    tx.input.encode(&mut buffer).unwrap();
+    tx.output.encode(&mut buffer).unwrap();
+    tx.lock_time.encode(&mut buffer).unwrap();
+    buffer
+}
+
+// strict DER (BIP 66) signature followed by the sighash type byte
+pub fn der_signature(signature: &ecdsa::Signature, sighash_type: u8) -> Vec<u8> {
+    let r = der_integer(&signature.r);
+    let s = der_integer(&signature.s);
+    let mut der = vec![0x30, (r.len() + s.len()) as u8];
+    der.extend(r);
+    der.extend(s);
+    der.push(sighash_type);
+    der
+}
+
+// minimal encoding: no leading zero bytes unless the next byte has its high bit set
+fn der_integer(bytes: &[u8; 32]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(31);
+    let mut integer = vec![0x02, 0];
+    if bytes[start] & 0x80 != 0 {
+        integer.push(0);
+    }
+    integer.extend_from_slice(&bytes[start..]);
+    integer[1] = (integer.len() - 2) as u8;
+    integer
+}
+
+// BIP 143 signature hash of input index, with sighash_type as serialized at the end of the preimage
+// the builder's own preimage only takes version 2 txs
+pub fn bip143_sighash(
+    tx: &BitcoinTransaction,
+    index: usize,
+    script_code: &ScriptBuf,
+    value: u64,
+    sighash_type: u32,
+) -> Vec<u8> {
+    let mut prevouts = vec![];
+    let mut sequences = vec![];
+    for input in &tx.input {
+        input.previous_output.encode(&mut prevouts).unwrap();
+        input.sequence.encode(&mut sequences).unwrap();
+    }
+    let mut outputs = vec![];
+    for output in &tx.output {
+        output.encode(&mut outputs).unwrap();
+    }
+
+    let mut preimage = vec![];
+    tx.version.encode(&mut preimage).unwrap();
+    preimage.extend(sha256d(prevouts));
+    preimage.extend(sha256d(sequences));
+    tx.input[index]
+        .previous_output
+        .encode(&mut preimage)
+        .unwrap();
+    script_code.encode(&mut preimage).unwrap();
+    preimage.extend(value.to_le_bytes());
+    tx.input[index].sequence.encode(&mut preimage).unwrap();
+    preimage.extend(sha256d(outputs));
+    tx.lock_time.encode(&mut preimage).unwrap();
+    preimage.extend(sighash_type.to_le_bytes());
+    sha256d(preimage)
+}
+
 pub fn get_encoded_tx(tx: BitcoinTransaction) -> Vec<u8> {
     tx.build_for_signing_legacy(EcdsaSighashType::All)
 }
 
-pub fn p2pkh_script_from_address(address: &str) -> ScriptBuf {
-    log!("address: {:?}", address);
-
-    let hash160 = &base58ck::decode_check(address).unwrap()[1..];
+pub fn p2pkh_script(hash160: &[u8]) -> ScriptBuf {
     // OP_DUP, OP_HASH160, ripemd160, OP_EQUALVERIFY, OP_CHECKSIG
     // len of hash160 should not overflow byte
     let mut script_pubkey: Vec<u8> = vec![0x76, 0xa9, hash160.len() as u8];
-    script_pubkey.extend_from_slice(&hash160[..]);
+    script_pubkey.extend_from_slice(hash160);
     script_pubkey.extend_from_slice(&[0x88, 0xac]);
 
-    log!("script_pubkey: {:?}", encode(&script_pubkey));
-
     ScriptBuf::from_bytes(script_pubkey)
 }
 
-pub fn p2pkh_script_from_ucp(uncompressed_child_pubkey: &str) -> ScriptBuf {
-    log!("uncompressed_child_pubkey: {:?}", uncompressed_child_pubkey);
+pub fn p2sh_script(hash160: &[u8]) -> ScriptBuf {
+    // OP_HASH160, script hash, OP_EQUAL
+    let mut script_pubkey: Vec<u8> = vec![0xa9, hash160.len() as u8];
+    script_pubkey.extend_from_slice(hash160);
+    script_pubkey.push(0x87);
+
+    ScriptBuf::from_bytes(script_pubkey)
+}
 
+pub fn hash160_from_ucp(uncompressed_child_pubkey: &str) -> Vec<u8> {
     let mut hasher = Ripemd160::new();
     hasher.update(sha256(&decode(uncompressed_child_pubkey).unwrap()));
-    let hash160 = hasher.finalize();
-    // log!("hash160: {:?}", hash160);
-    // OP_DUP, OP_HASH160, ripemd160, OP_EQUALVERIFY, OP_CHECKSIG
-    // len of hash160 should not overflow byte
-    let mut script_pubkey: Vec<u8> = vec![0x76, 0xa9, hash160.len() as u8];
-    script_pubkey.extend_from_slice(&hash160[..]);
-    script_pubkey.extend_from_slice(&[0x88, 0xac]);
+    hasher.finalize().to_vec()
+}
 
-    log!("script_pubkey: {:?}", encode(&script_pubkey));
+pub fn p2pkh_address_from_ucp(uncompressed_child_pubkey: &str, version: u8) -> String {
+    let mut data = vec![version];
+    data.extend(hash160_from_ucp(uncompressed_child_pubkey));
+    base58ck::encode_check(&data)
+}
 
-    ScriptBuf::from_bytes(script_pubkey)
+pub fn p2pkh_script_from_ucp(uncompressed_child_pubkey: &str) -> ScriptBuf {
+    p2pkh_script(&hash160_from_ucp(uncompressed_child_pubkey))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn get_tx(
     txid_str: &str,
     vout: u32,
     funder: &str,
-    receiver: &str,
+    receiver_script_pubkey: ScriptBuf,
     amount: u128,
-    change: u128,
-    op_return_script: Option<Vec<u8>>,
+    // no change output when the claim sweeps the utxo
+    change: Option<u128>,
+    _op_return_script: Option<Vec<u8>>,
+    version: Version,
 ) -> BitcoinTransaction {
     let hash = Hash::from_hex(txid_str).unwrap();
     let txid = Txid(hash);
 
     let funder_script_pubkey = p2pkh_script_from_ucp(funder);
-    let receiver_script_pubkey = p2pkh_script_from_address(receiver);
 
     let txin: TxIn = TxIn {
         previous_output: OutPoint::new(txid, vout),
@@ -86,13 +195,13 @@ pub fn get_tx(
     outputs.push(spend_txout);
 
     // The change output: utxo amount - amount - fee, locked to key controlled by the funder
-    let change_txout = TxOut {
-        value: Amount::from_sat(change as u64),
-        script_pubkey: funder_script_pubkey,
-    };
-    outputs.push(change_txout);
-
-    log!("outputs {:?}", outputs);
+    if let Some(change) = change {
+        let change_txout = TxOut {
+            value: Amount::from_sat(change as u64),
+            script_pubkey: funder_script_pubkey,
+        };
+        outputs.push(change_txout);
+    }
 
     // // OP_RETURN
     // if op_return_script.is_some() {
@@ -104,7 +213,7 @@ pub fn get_tx(
     // }
 
     TransactionBuilder::new::<BITCOIN>()
-        .version(Version::One)
+        .version(version)
         .inputs(vec![txin])
         .outputs(outputs)
         .lock_time(LockTime::from_height(0).unwrap())
@@ -121,40 +230,42 @@ impl Contract {
         #[callback_result] call_result: Result<external::SignatureResponse, PromiseError>,
         bitcoin_tx: BitcoinTransaction,
         bitcoin_pubkey: Vec<u8>,
-    ) -> String {
-        self.remove_key_callback();
-
-        match call_result {
-            Ok(signature_response) => {
-                env::log_str(&format!(
-                    "Successfully received signature: big_r = {:?}, s = {:?}, recovery_id = {}",
-                    signature_response.big_r, signature_response.s, signature_response.recovery_id
-                ));
-
-                let signature = serialize_ecdsa_signature_from_str(
-                    &signature_response.big_r.affine_point,
-                    &signature_response.s.scalar,
-                );
+        amount: U128,
+        network: Network,
+        value: Option<U128>,
+    ) -> claims::ClaimResponse {
+        let chain = network.chain();
+        // checked by claim
+        let sighash = chain
+            .sighash(&bitcoin_tx, value.map(|value| value.0))
+            .unwrap();
+        let signature =
+            call_result.map(|signature_response| ecdsa::normalize_signature(&signature_response));
+        let signed_tx = match signature {
+            // a signature from a misconfigured signer or for another path must never be broadcast
+            Ok(Some(signature))
+                if ecdsa::verify_signature(&sighash, &signature, &bitcoin_pubkey) =>
+            {
+                let signature = der_signature(&signature, chain.sighash_type());
 
                 let script_sig = build_script_sig(&signature, bitcoin_pubkey.as_slice());
 
                 let mut bitcoin_tx = bitcoin_tx;
 
                 // Update the transaction with the script_sig
-                let updated_tx = bitcoin_tx.build_with_script_sig(
-                    0,
-                    ScriptBuf(script_sig),
-                    TransactionType::P2PKH,
-                );
-
-                // Serialise the updated transaction
-                hex::encode(updated_tx)
+                bitcoin_tx.build_with_script_sig(0, ScriptBuf(script_sig), TransactionType::P2PKH);
+
+                Ok(SignedTx::new(&bitcoin_tx))
             }
+            Ok(_) => Err(claims::ClaimError::InvalidSignature),
             Err(error) => {
                 env::log_str(&format!("Callback failed with error: {:?}", error));
-                "Callback failed".to_string()
+                Err(claims::ClaimError::SignatureFailed)
             }
-        }
+        };
+        self.settle_claim(&signed_tx, amount.0);
+
+        claims::ClaimResponse::new(network, signed_tx)
     }
 }
 
@@ -177,7 +288,190 @@ fn test_p2pkh_script_from_ucp() {
 fn test_p2pkh_script_from_address() {
     let address = "mwVgE7n7nwtc3TtTDxN8c2gntFtVpBwBtK";
     let script_sig = decode("76a914af442f0d61233c9d3fdde22d36bfb6e3e441689088ac").unwrap();
-    let test_script_sig = p2pkh_script_from_address(address);
+    let test_script_sig = network::BITCOIN_TESTNET.script_pubkey(address).unwrap();
 
     assert!(script_sig == test_script_sig.0);
 }
+
+#[test]
+fn test_p2pkh_address_from_ucp() {
+    let ucp = "048393e4b554ced50402b2e9fcf765941fcbf3fa2b87c450873a0127dbb8cd7d214a4be00c690901a0eae20e50faf1957f30aecd9e34c7395d1f7bdb5d79123d8a";
+    assert_eq!(
+        p2pkh_address_from_ucp(ucp, network::BITCOIN_TESTNET.0.p2pkh_version),
+        "mwVgE7n7nwtc3TtTDxN8c2gntFtVpBwBtK"
+    );
+}
+
+#[test]
+fn test_callback_rejects_foreign_signature() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    let response = callback(&mut contract, foreign_signature_result());
+    assert_eq!(response.status, claims::ClaimStatus::Failed);
+    assert_eq!(
+        response.error.unwrap().code,
+        claims::ClaimError::InvalidSignature
+    );
+    // the key can claim again
+    assert_eq!(
+        contract.get_key_status(test_key()),
+        Some(keys::KeyStatus::Active)
+    );
+    let record = contract.get_claim_by_key(test_key()).unwrap();
+    assert_eq!(record.error, Some(claims::ClaimError::InvalidSignature));
+
+    claim(&mut contract, &test_key());
+    let response = callback(&mut contract, signature_result());
+    assert_eq!(response.status, claims::ClaimStatus::Signed);
+    assert_eq!(response.chain, "bitcoin-testnet");
+    assert!(response.signed_tx.unwrap().starts_with("0100000001"));
+    let txid = response.txid.unwrap();
+    assert_eq!(
+        response.explorer_hint.unwrap(),
+        format!("https://mempool.space/testnet/tx/{}", txid)
+    );
+    assert_eq!(contract.get_key_status(test_key()), None);
+}
+
+#[test]
+fn test_der_signature_is_minimal() {
+    let mut r = [0u8; 32];
+    r[1] = 0x7f;
+    let mut s = [0xffu8; 32];
+    s[0] = 0x7f;
+    let der = der_signature(
+        &ecdsa::Signature {
+            r,
+            s,
+            recovery_id: 0,
+        },
+        EcdsaSighashType::All as u8,
+    );
+    assert!(is_strict_der(&der));
+    // r drops its leading zero, s keeps 32 bytes
+    assert_eq!(&der[..4], &[0x30, 67, 0x02, 31]);
+    assert_eq!(&der[35..37], &[0x02, 32]);
+
+    r[1] = 0x80;
+    let der = der_signature(
+        &ecdsa::Signature {
+            r,
+            s,
+            recovery_id: 0,
+        },
+        EcdsaSighashType::All as u8,
+    );
+    assert!(is_strict_der(&der));
+    assert_eq!(&der[2..5], &[0x02, 32, 0x00]);
+}
+
+#[test]
+fn test_callback_normalizes_high_s() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    let response = callback(&mut contract, high_s_signature_result());
+    let signed_tx = decode(response.signed_tx.unwrap()).unwrap();
+    // version, input count, outpoint, script_sig length, signature push
+    let der_len = signed_tx[42] as usize;
+    let der = &signed_tx[43..43 + der_len];
+    assert!(is_strict_der(der));
+    let s = &der[der.len() - 33..der.len() - 1];
+    assert_eq!(
+        encode(s),
+        "721e7d47f4da77baf00c3875cc2caa84bc10576023973e876542e54cec52b3c4"
+    );
+}
+
+#[test]
+fn test_txid() {
+    let mut tx = tx();
+    let legacy = SignedTx::new(&tx);
+    assert_eq!(legacy.txid, reversed_hex(sha256d(tx.serialize())));
+    assert!(legacy.wtxid.is_none());
+
+    // the witness changes the wtxid but not the txid
+    tx.input[0].witness = Witness::from_slice(&[vec![1u8; 72], vec![2u8; 33]]);
+    let segwit = SignedTx::new(&tx);
+    assert_eq!(segwit.txid, legacy.txid);
+    assert_ne!(tx.serialize(), encode_without_witness(&tx));
+    assert_eq!(segwit.wtxid.unwrap(), reversed_hex(sha256d(tx.serialize())));
+}
+
+#[test]
+fn test_callback_bitcoin_cash() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        546,
+        DropOptions::default(),
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    let receiver = network::BITCOIN_CASH_TESTNET.funder_address(TEST_FUNDER);
+
+    set_claim_context(&test_key());
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: receiver.clone(),
+        change: Some(U128(1000)),
+        value: Some(U128(10_000)),
+        fee: None,
+    });
+    let tx = get_tx(
+        TEST_TXID,
+        0,
+        TEST_FUNDER,
+        network::BITCOIN_CASH_TESTNET
+            .script_pubkey(&receiver)
+            .unwrap(),
+        546,
+        Some(1000),
+        None,
+        Version::Two,
+    );
+    let response = contract.callback(
+        bitcoin_cash_signature_result(),
+        tx,
+        decode(TEST_FUNDER).unwrap(),
+        U128(546),
+        Network::BitcoinCashTestnet,
+        Some(U128(10_000)),
+    );
+    assert_eq!(response.status, claims::ClaimStatus::Signed);
+    assert_eq!(response.chain, "bitcoin-cash-testnet");
+    assert!(response.explorer_hint.is_none());
+    let signed_tx = decode(response.signed_tx.unwrap()).unwrap();
+    assert_eq!(signed_tx[..4], [2, 0, 0, 0]);
+    // the signature ends with SIGHASH_ALL | SIGHASH_FORKID
+    let der_len = signed_tx[42] as usize;
+    assert_eq!(signed_tx[42 + der_len], 0x41);
+    assert!(is_strict_der(&signed_tx[43..43 + der_len]));
+}
+
+#[test]
+fn test_sweep_tx() {
+    assert_eq!(max_signed_size(&tx()), network::CLAIM_TX_MAX_SIZE);
+    let receiver = network::BITCOIN_TESTNET
+        .script_pubkey(TEST_RECEIVER)
+        .unwrap();
+    let tx = get_tx(
+        TEST_TXID,
+        0,
+        TEST_FUNDER,
+        receiver,
+        9_500,
+        None,
+        None,
+        Version::One,
+    );
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].value, Amount::from_sat(9_500));
+    assert_eq!(max_signed_size(&tx), network::SWEEP_TX_MAX_SIZE);
+}
@@ -0,0 +1,338 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+// hex txid and wtxid written to the record once the claim is signed,
+// a failed claim writes its error instead which is smaller
+pub const CLAIM_TX_HASH_BYTES: u64 = 2 * (4 + 64);
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClaimStatus {
+    // waiting for the signature
+    Pending,
+    Signed,
+    Failed,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClaimError {
+    // the MPC sign call failed
+    SignatureFailed,
+    // the signature doesn't recover to the drop funder
+    InvalidSignature,
+}
+
+impl ClaimError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ClaimError::SignatureFailed => "the MPC signer did not return a signature",
+            ClaimError::InvalidSignature => "the signature does not match the drop funder",
+        }
+    }
+}
+
+#[near(serializers = [json])]
+pub struct ClaimErrorResponse {
+    pub code: ClaimError,
+    pub message: String,
+}
+
+// returned by the claim callback, either signed with the tx or failed with the error
+#[near(serializers = [json])]
+pub struct ClaimResponse {
+    pub status: ClaimStatus,
+    pub chain: String,
+    // hex of the signed tx, ready to broadcast
+    pub signed_tx: Option<String>,
+    pub txid: Option<String>,
+    // only for segwit txs
+    pub wtxid: Option<String>,
+    // block explorer url of the tx once broadcast
+    pub explorer_hint: Option<String>,
+    pub error: Option<ClaimErrorResponse>,
+}
+
+impl ClaimResponse {
+    pub fn new(network: Network, result: Result<bitcoin_tx::SignedTx, ClaimError>) -> Self {
+        let params = network.chain().params();
+        let chain = params.name.to_string();
+        match result {
+            Ok(signed_tx) => ClaimResponse {
+                status: ClaimStatus::Signed,
+                chain,
+                signed_tx: Some(encode(signed_tx.tx)),
+                explorer_hint: params.explorer_url(&signed_tx.txid),
+                txid: Some(signed_tx.txid),
+                wtxid: signed_tx.wtxid,
+                error: None,
+            },
+            Err(error) => ClaimResponse {
+                status: ClaimStatus::Failed,
+                chain,
+                signed_tx: None,
+                txid: None,
+                wtxid: None,
+                explorer_hint: None,
+                error: Some(ClaimErrorResponse {
+                    code: error,
+                    message: error.message().to_string(),
+                }),
+            },
+        }
+    }
+}
+
+// every claim is recorded, including failed ones, and kept after its key or drop is removed
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct ClaimRecord {
+    pub drop_id: U128,
+    pub key: PublicKey,
+    pub receiver: String,
+    // the utxo spent by the claim
+    pub utxo_txid: String,
+    pub utxo_vout: u32,
+    pub amount: U128,
+    // txid of the signed claim tx, set once signed
+    pub txid: Option<String>,
+    // only for segwit txs
+    pub wtxid: Option<String>,
+    pub block_height: u64,
+    pub status: ClaimStatus,
+    pub error: Option<ClaimError>,
+}
+
+// the args of the claim method, their json schema is returned by get_claim_info,
+// amounts are in the smallest unit of the drop's network, as decimal strings
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimArgs {
+    /// hex txid of a utxo of the funder address, recorded as utxo_txid
+    pub txid_str: String,
+    /// output index of the utxo, recorded as utxo_vout
+    pub vout: u32,
+    /// p2pkh, p2sh, segwit (litecoin) or CashAddr (bitcoin cash) address of the drop's network receiving amount
+    pub receiver: String,
+    /// utxo value - amount - fee, returned to the funder address, at least the dust limit, unless the drop sweeps
+    #[schemars(with = "Option<String>")]
+    pub change: Option<U128>,
+    /// value of the spent utxo, required by sweep drops and on bitcoin cash
+    #[schemars(with = "Option<String>")]
+    pub value: Option<U128>,
+    /// fee of a sweep claim, the receiver gets value - fee
+    #[schemars(with = "Option<String>")]
+    pub fee: Option<U128>,
+}
+
+// what a key can claim, for clients that only hold the drop secret key
+#[near(serializers = [json])]
+pub struct ClaimInfo {
+    pub drop_id: U128,
+    pub network: Network,
+    pub amount: U128,
+    // uncompressed public key the claim spends from, and its p2pkh address
+    pub funder: String,
+    pub funder_address: String,
+    // smallest change output and fee the claim tx is relayed with
+    pub dust_limit: U128,
+    pub min_fee: U128,
+    // sweep claims spend a whole utxo of at most amount, with a fee of at most max_fee
+    pub sweep: bool,
+    pub max_fee: Option<U128>,
+    pub status: keys::KeyStatus,
+    pub remaining_uses: u32,
+    pub starts_at: Option<U64>,
+    pub expires_at: Option<U64>,
+    pub method: String,
+    // json schema of ClaimArgs
+    pub args: near_sdk::serde_json::Value,
+}
+
+impl Contract {
+    pub(crate) fn insert_claim_record(&mut self, drop: &mut Drop, record: ClaimRecord) {
+        self.claim_id += 1;
+        self.drop_claims
+            .insert((record.drop_id.0, drop.record_count), self.claim_id);
+        self.claim_by_key.insert(record.key.clone(), self.claim_id);
+        self.claim_by_id.insert(self.claim_id, record);
+        drop.record_count += 1;
+    }
+
+    // settles the claim in flight for key with the signed tx or the error
+    pub(crate) fn finish_claim_record(
+        &mut self,
+        key: &PublicKey,
+        result: &Result<bitcoin_tx::SignedTx, ClaimError>,
+    ) {
+        let claim_id = match self.claim_by_key.get(key) {
+            Some(claim_id) => *claim_id,
+            None => return,
+        };
+        let record = self.claim_by_id.get_mut(&claim_id).unwrap();
+        let drop_id = record.drop_id;
+        let event = match result {
+            Ok(signed_tx) => {
+                record.status = ClaimStatus::Signed;
+                record.txid = Some(signed_tx.txid.clone());
+                record.wtxid = signed_tx.wtxid.clone();
+                events::Event::ClaimSigned {
+                    drop_id,
+                    claim_id: U64(claim_id),
+                    key: key.clone(),
+                    txid: signed_tx.txid.clone(),
+                    wtxid: signed_tx.wtxid.clone(),
+                }
+            }
+            Err(error) => {
+                record.status = ClaimStatus::Failed;
+                record.error = Some(*error);
+                events::Event::ClaimFailed {
+                    drop_id,
+                    claim_id: U64(claim_id),
+                    key: key.clone(),
+                    error: *error,
+                }
+            }
+        };
+        event.emit();
+    }
+}
+
+#[near]
+impl Contract {
+    // views
+
+    // claims of the drop in the order they were made, also after the drop was deleted
+    pub fn get_claims(
+        &self,
+        drop_id: U128,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<ClaimRecord> {
+        (from_index.unwrap_or(0)..)
+            .map_while(|index| self.drop_claims.get(&(drop_id.0, index)))
+            .take(limit.unwrap_or(DEFAULT_VIEW_LIMIT) as usize)
+            .map(|claim_id| self.claim_by_id.get(claim_id).unwrap().clone())
+            .collect()
+    }
+
+    // None when the key is not registered
+    pub fn get_claim_info(&self, public_key: PublicKey) -> Option<ClaimInfo> {
+        let drop_key = self.drop_by_key.get(&public_key)?;
+        let drop = self.get_drop(U128(drop_key.drop_id)).unwrap();
+        let chain = drop.network.chain();
+        Some(ClaimInfo {
+            drop_id: drop.drop_id,
+            network: drop.network,
+            amount: drop.amount,
+            funder_address: chain.funder_address(&drop.funder),
+            dust_limit: U128(chain.params().dust_limit),
+            min_fee: U128(chain.params().min_fee(if drop.sweep {
+                network::SWEEP_TX_MAX_SIZE
+            } else {
+                network::CLAIM_TX_MAX_SIZE
+            })),
+            sweep: drop.sweep,
+            max_fee: drop.max_fee,
+            funder: drop.funder,
+            status: drop_key.status,
+            remaining_uses: drop_key.remaining_uses,
+            starts_at: drop.starts_at,
+            expires_at: drop.expires_at,
+            method: ACCESS_KEY_METHODS.to_string(),
+            args: near_sdk::serde_json::to_value(schemars::schema_for!(ClaimArgs)).unwrap(),
+        })
+    }
+
+    // the latest claim made with the key
+    pub fn get_claim_by_key(&self, key: PublicKey) -> Option<ClaimRecord> {
+        self.claim_by_key
+            .get(&key)
+            .map(|claim_id| self.claim_by_id.get(claim_id).unwrap().clone())
+    }
+}
+
+#[test]
+fn test_claim_records() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    let record = contract.get_claim_by_key(test_key()).unwrap();
+    assert_eq!(record.status, ClaimStatus::Pending);
+    assert_eq!(record.receiver, TEST_RECEIVER);
+    assert_eq!(record.utxo_txid, TEST_TXID);
+    assert!(record.txid.is_none());
+
+    callback(&mut contract, Err(PromiseError::Failed));
+    let record = contract.get_claim_by_key(test_key()).unwrap();
+    assert_eq!(record.status, ClaimStatus::Failed);
+    assert_eq!(record.error, Some(ClaimError::SignatureFailed));
+
+    claim(&mut contract, &test_key());
+    callback(&mut contract, signature_result());
+    let record = contract.get_claim_by_key(test_key()).unwrap();
+    assert_eq!(record.status, ClaimStatus::Signed);
+    assert_eq!(record.txid.unwrap().len(), 64);
+    assert!(record.wtxid.is_none());
+    assert!(near_sdk::test_utils::get_logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"claim_signed""#)));
+
+    let claims = contract.get_claims(drop_id, None, None);
+    assert_eq!(claims.len(), 2);
+    assert_eq!(claims[0].status, ClaimStatus::Failed);
+    assert_eq!(contract.get_claims(drop_id, Some(1), None).len(), 1);
+    assert!(contract.get_claims(drop_id, None, Some(0)).is_empty());
+}
+
+#[test]
+fn test_get_claim_info() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    assert!(contract.get_claim_info(test_keys(1)[0].clone()).is_none());
+
+    let info = contract.get_claim_info(test_key()).unwrap();
+    assert_eq!(info.drop_id, drop_id);
+    assert_eq!(info.amount, U128(546));
+    assert_eq!(
+        info.funder_address,
+        bitcoin_tx::p2pkh_address_from_ucp(TEST_FUNDER, 0x6f)
+    );
+    assert_eq!(info.network, Network::BitcoinTestnet);
+    assert_eq!(info.dust_limit, U128(546));
+    assert_eq!(info.min_fee, U128(259));
+    assert_eq!(info.remaining_uses, 2);
+    assert_eq!(info.status, keys::KeyStatus::Active);
+    assert_eq!(info.method, "claim");
+    let properties = info.args["properties"].as_object().unwrap();
+    assert_eq!(
+        properties.keys().collect::<Vec<_>>(),
+        ["change", "fee", "receiver", "txid_str", "value", "vout"]
+    );
+    assert_eq!(
+        info.args["required"],
+        near_sdk::serde_json::json!(["receiver", "txid_str", "vout"])
+    );
+    assert_eq!(
+        properties["vout"]["description"],
+        "output index of the utxo, recorded as utxo_vout"
+    );
+    assert!(!info.sweep);
+}
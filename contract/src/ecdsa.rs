@@ -1,9 +1,13 @@
 use crate::*;
 use external::{mpc_contract, SignRequest};
+use k256::elliptic_curve::scalar::IsHigh;
+use k256::elliptic_curve::PrimeField;
+use k256::{FieldBytes, Scalar};
 
 const MPC_CONTRACT_ACCOUNT_ID: &str = "v1.signer-prod.testnet";
 const GAS: Gas = Gas::from_tgas(100);
-const ATTACHED_DEPOSIT: NearToken = NearToken::from_yoctonear(500000000000000000000000);
+const KEY_VERSION_GAS: Gas = Gas::from_tgas(10);
+pub const ATTACHED_DEPOSIT: NearToken = NearToken::from_yoctonear(500000000000000000000000);
 
 pub fn get_sig(payload: Vec<u8>, path: String, key_version: u32) -> Promise {
     let request = SignRequest {
@@ -16,3 +20,152 @@ pub fn get_sig(payload: Vec<u8>, path: String, key_version: u32) -> Promise {
         .with_attached_deposit(ATTACHED_DEPOSIT)
         .sign(request)
 }
+
+#[near]
+impl Contract {
+    // owner methods
+
+    // registers the root public key of an MPC key version, so drops can be created for it
+    // before or after the signer rotates to it
+    pub fn add_mpc_public_key(&mut self, key_version: u32, public_key: PublicKey) {
+        require!(
+            env::predecessor_account_id() == self.owner_id,
+            "only the owner can call this method"
+        );
+        kdf::public_key_to_point(&public_key);
+        require!(
+            !self.mpc_public_keys.contains_key(&key_version),
+            "key_version already registered"
+        );
+        self.mpc_public_keys.insert(key_version, public_key);
+    }
+
+    // anyone can sync the default key version of new drops with the signer
+    pub fn update_mpc_key_version(&mut self) -> Promise {
+        mpc_contract::ext(MPC_CONTRACT_ACCOUNT_ID.parse().unwrap())
+            .with_static_gas(KEY_VERSION_GAS)
+            .latest_key_version()
+            .then(
+                external::this_contract::ext(env::current_account_id())
+                    .with_static_gas(KEY_VERSION_GAS)
+                    .on_latest_key_version(),
+            )
+    }
+
+    // the latest version is only used once its root public key is registered
+    #[private]
+    pub fn on_latest_key_version(
+        &mut self,
+        #[callback_result] key_version: Result<u32, PromiseError>,
+    ) -> u32 {
+        match key_version {
+            Ok(key_version) if self.mpc_public_keys.contains_key(&key_version) => {
+                self.mpc_key_version = key_version;
+            }
+            Ok(key_version) => log!("no public key registered for key_version {}", key_version),
+            Err(_) => log!("latest_key_version failed"),
+        }
+        self.mpc_key_version
+    }
+
+    // views
+
+    pub fn get_mpc_key_version(&self) -> u32 {
+        self.mpc_key_version
+    }
+
+    pub fn get_mpc_public_key(&self, key_version: Option<u32>) -> Option<PublicKey> {
+        self.mpc_public_keys
+            .get(&key_version.unwrap_or(self.mpc_key_version))
+            .cloned()
+    }
+}
+
+// an MPC signature with s in the lower half of the curve order
+pub struct Signature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    pub recovery_id: u8,
+}
+
+// None when big_r or s are malformed
+// a high s is replaced by n - s, which recovers with the opposite parity of big_r
+pub fn normalize_signature(response: &external::SignatureResponse) -> Option<Signature> {
+    let big_r = decode(&response.big_r.affine_point).ok()?;
+    let s = decode(&response.s.scalar).ok()?;
+    if big_r.len() != 33 || s.len() != 32 {
+        return None;
+    }
+    let mut s: Scalar = Option::from(Scalar::from_repr(FieldBytes::clone_from_slice(&s)))?;
+    let mut recovery_id = response.recovery_id;
+    if bool::from(s.is_high()) {
+        s = -s;
+        recovery_id ^= 1;
+    }
+    // r is the x coordinate of the compressed big_r
+    Some(Signature {
+        r: big_r[1..].try_into().unwrap(),
+        s: s.to_bytes().into(),
+        recovery_id,
+    })
+}
+
+// true when the signature over payload recovers to the uncompressed public key
+// ecrecover rejects high s, so only normalized signatures pass
+pub fn verify_signature(payload: &[u8], signature: &Signature, public_key: &[u8]) -> bool {
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&signature.r);
+    sig[32..].copy_from_slice(&signature.s);
+    env::ecrecover(payload, &sig, signature.recovery_id, true)
+        .is_some_and(|recovered| public_key.len() == 65 && recovered[..] == public_key[1..])
+}
+
+#[test]
+fn test_key_versions() {
+    let mut contract = setup("alice.testnet");
+    set_context("owner.testnet", NearToken::from_near(0));
+    // 8 * G
+    let next: PublicKey = "secp256k1:wWasVKEQkJEPad4X6NU3WZpmTxcwAfH1Dk4zhaD5aT4DYaQtEpvjsSYbMZBiDc32UF8vAD82jFf68q98Sgujd5V"
+        .parse()
+        .unwrap();
+    contract.add_mpc_public_key(1, next.clone());
+
+    // the signer hasn't rotated yet
+    set_context("linkdrop.testnet", NearToken::from_near(0));
+    assert_eq!(contract.on_latest_key_version(Ok(0)), 0);
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    assert_eq!(contract.get_drop(drop_id).unwrap().funder, TEST_FUNDER);
+
+    set_context("linkdrop.testnet", NearToken::from_near(0));
+    assert_eq!(contract.on_latest_key_version(Ok(2)), 0);
+    assert_eq!(contract.on_latest_key_version(Ok(1)), 1);
+    assert_eq!(contract.get_mpc_public_key(None), Some(next));
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let drop = contract.get_drop(drop_id).unwrap();
+    assert_eq!(drop.key_version, 1);
+    assert_ne!(drop.funder, TEST_FUNDER);
+}
+
+#[test]
+fn test_normalize_high_s() {
+    let low = normalize_signature(&signature_result().unwrap()).unwrap();
+    assert_eq!(low.recovery_id, 1);
+    assert_eq!(
+        encode(low.s),
+        "7916bdfee8052137dff82eee1e3adff1cce0e3862dd941f9040906cc1c98808f"
+    );
+
+    let high = high_s_signature_result().unwrap();
+    let normalized = normalize_signature(&high).unwrap();
+    assert_eq!(normalized.recovery_id, high.recovery_id ^ 1);
+    assert_eq!(
+        encode(normalized.s),
+        "721e7d47f4da77baf00c3875cc2caa84bc10576023973e876542e54cec52b3c4"
+    );
+    let payload = bitcoin_tx::sha256d(bitcoin_tx::get_encoded_tx(tx()));
+    assert!(verify_signature(
+        &payload,
+        &normalized,
+        &decode(TEST_FUNDER).unwrap()
+    ));
+}
@@ -0,0 +1,139 @@
+use crate::*;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{self, Value};
+
+// NEP-297 events, logged as EVENT_JSON:{"standard","version","event","data"}
+// bump the version on any change to the data of an event
+pub const EVENT_STANDARD: &str = "btc-linkdrop";
+pub const EVENT_VERSION: &str = "1.2.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DropChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    // the settings of the drop, the counters all start at 0
+    DropCreated {
+        drop_id: U128,
+        creator: AccountId,
+        network: Network,
+        funder: String,
+        path: String,
+        key_version: u32,
+        amount: U128,
+        uses_per_key: u32,
+        max_claims: Option<u64>,
+        total_budget: Option<U128>,
+        starts_at: Option<U64>,
+        expires_at: Option<U64>,
+        sweep: bool,
+        max_fee: Option<U128>,
+    },
+    DropUpdated {
+        drop_id: U128,
+        changes: Vec<DropChange>,
+    },
+    DropDeleted {
+        drop_id: U128,
+        creator: AccountId,
+    },
+    // keys whose access keys were created
+    KeyAdded {
+        drop_id: U128,
+        keys: Vec<PublicKey>,
+    },
+    // keys whose access keys are being deleted
+    KeyRemoved {
+        drop_id: U128,
+        keys: Vec<PublicKey>,
+    },
+    ClaimStarted {
+        drop_id: U128,
+        claim_id: U64,
+        key: PublicKey,
+        receiver: String,
+        amount: U128,
+    },
+    ClaimSigned {
+        drop_id: U128,
+        claim_id: U64,
+        key: PublicKey,
+        txid: String,
+        // only for segwit txs
+        wtxid: Option<String>,
+    },
+    ClaimFailed {
+        drop_id: U128,
+        claim_id: U64,
+        key: PublicKey,
+        error: claims::ClaimError,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self,
+        };
+        format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap())
+    }
+
+    pub fn emit(&self) {
+        env::log_str(&self.to_json());
+    }
+}
+
+#[test]
+fn test_event_json() {
+    let event = Event::DropDeleted {
+        drop_id: U128(1),
+        creator: "alice.testnet".parse().unwrap(),
+    };
+    assert_eq!(
+        event.to_json(),
+        r#"EVENT_JSON:{"standard":"btc-linkdrop","version":"1.2.0","event":"drop_deleted","data":{"drop_id":"1","creator":"alice.testnet"}}"#
+    );
+}
+
+#[test]
+fn test_drop_created_event() {
+    let mut contract = setup("alice.testnet");
+    add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            max_claims: Some(10),
+            ..Default::default()
+        },
+    );
+    let log = near_sdk::test_utils::get_logs()
+        .into_iter()
+        .find(|log| log.contains(r#""event":"drop_created""#))
+        .unwrap();
+    let data =
+        &serde_json::from_str::<Value>(log.strip_prefix("EVENT_JSON:").unwrap()).unwrap()["data"];
+    assert_eq!(data["network"], "BitcoinTestnet");
+    assert_eq!(data["uses_per_key"], 2);
+    assert_eq!(data["max_claims"], 10);
+    assert_eq!(data["total_budget"], Value::Null);
+    assert_eq!(data["sweep"], false);
+    assert_eq!(data["key_version"], 0);
+}
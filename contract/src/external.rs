@@ -38,10 +38,20 @@ pub struct SignRequest {
 trait MPCContract {
     fn sign(&self, request: SignRequest);
     fn experimental_signature_deposit(&self) -> NearToken;
+    fn latest_key_version(&self) -> u32;
 }
 
 #[allow(dead_code)]
 #[ext_contract(this_contract)]
 trait ThisContract {
-    fn callback(&self, bitcoin_tx: BitcoinTransaction, bitcoin_pubkey: Vec<u8>);
+    fn callback(
+        &self,
+        bitcoin_tx: BitcoinTransaction,
+        bitcoin_pubkey: Vec<u8>,
+        amount: U128,
+        network: Network,
+        value: Option<U128>,
+    );
+    fn on_keys_added(&mut self, drop_id: U128, keys: Vec<PublicKey>) -> bool;
+    fn on_latest_key_version(&mut self) -> u32;
 }
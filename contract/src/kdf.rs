@@ -0,0 +1,49 @@
+use crate::*;
+use k256::elliptic_curve::ops::Reduce;
+use k256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use k256::{AffinePoint, EncodedPoint, ProjectivePoint, Scalar, U256};
+use near_sdk::CurveType;
+use sha3::{Digest, Sha3_256};
+
+// the MPC signer derives a child key per (predecessor, path), see tests/kdf.js
+const EPSILON_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 epsilon derivation:";
+
+pub fn derive_epsilon(predecessor: &AccountId, path: &str) -> Scalar {
+    let hash = Sha3_256::digest(format!(
+        "{}{},{}",
+        EPSILON_DERIVATION_PREFIX, predecessor, path
+    ));
+    <Scalar as Reduce<U256>>::reduce_bytes(&hash)
+}
+
+pub fn public_key_to_point(public_key: &PublicKey) -> AffinePoint {
+    require!(
+        public_key.curve_type() == CurveType::SECP256K1,
+        "MPC public key must be secp256k1"
+    );
+    // near secp256k1 keys are x || y, prefix them as sec1 uncompressed
+    let mut bytes = vec![0x04];
+    bytes.extend_from_slice(&public_key.as_bytes()[1..]);
+    let point = EncodedPoint::from_bytes(bytes).expect("invalid MPC public key");
+    Option::from(AffinePoint::from_encoded_point(&point)).expect("invalid MPC public key")
+}
+
+// child = root + epsilon * G, as uncompressed hex "04" + x + y
+pub fn derive_child_public_key(root: &PublicKey, predecessor: &AccountId, path: &str) -> String {
+    let epsilon = derive_epsilon(predecessor, path);
+    let child = ProjectivePoint::GENERATOR * epsilon + public_key_to_point(root);
+    encode(child.to_affine().to_encoded_point(false).as_bytes())
+}
+
+#[test]
+fn test_derive_child_public_key() {
+    let root: PublicKey = TEST_MPC_PUBLIC_KEY.parse().unwrap();
+    assert_eq!(
+        derive_child_public_key(
+            &root,
+            &"linkdrop.testnet".parse().unwrap(),
+            "alice.testnet/bitcoin-1"
+        ),
+        TEST_FUNDER
+    );
+}
@@ -0,0 +1,223 @@
+use crate::*;
+
+// drop keys are stored by (drop_id, index) so adding or removing a key
+// never reads or rewrites the other keys of the drop
+#[near(serializers = [borsh])]
+#[derive(Clone)]
+pub struct DropKey {
+    pub drop_id: u128,
+    pub index: u64,
+    pub status: KeyStatus,
+    pub remaining_uses: u32,
+    // yoctoNEAR left of the claim record storage reserved with the key
+    pub record_reserve: u128,
+    // set by the first claim, whose gas the access key allowance paid for
+    pub claimed: bool,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyStatus {
+    // registered, waiting for the AddKey batch to land
+    Pending,
+    // the access key exists and can claim
+    Active,
+    // a claim is waiting for its signature
+    Claiming,
+}
+
+impl Contract {
+    pub(crate) fn insert_drop_key(&mut self, drop_id: u128, drop: &mut Drop, key: PublicKey) {
+        let index = drop.key_count;
+        self.drop_keys.insert((drop_id, index), key.clone());
+        self.drop_by_key.insert(
+            key,
+            DropKey {
+                drop_id,
+                index,
+                status: KeyStatus::Pending,
+                remaining_uses: drop.uses_per_key,
+                record_reserve: storage::storage_cost(storage::CLAIM_RECORD_STORAGE_BYTES)
+                    * drop.uses_per_key as u128,
+                claimed: false,
+            },
+        );
+        drop.key_count += 1;
+    }
+
+    // swap remove, the last key of the drop takes the index of the removed key
+    pub(crate) fn remove_drop_key(&mut self, drop: &mut Drop, key: &PublicKey) -> Option<DropKey> {
+        let drop_key = self.drop_by_key.remove(key)?;
+        let last = drop.key_count - 1;
+        let last_key = self.drop_keys.remove(&(drop_key.drop_id, last)).unwrap();
+        if drop_key.index != last {
+            self.drop_by_key.get_mut(&last_key).unwrap().index = drop_key.index;
+            self.drop_keys
+                .insert((drop_key.drop_id, drop_key.index), last_key);
+        }
+        drop.key_count -= 1;
+        Some(drop_key)
+    }
+
+    // the unused reservation of the key: its access key storage, the signature deposits of its
+    // remaining uses, what is left of its claim record storage, and the allowance until it has claimed
+    pub(crate) fn key_refund(&self, key: &PublicKey) -> u128 {
+        let drop_key = self.drop_by_key.get(key).unwrap();
+        let allowance = if drop_key.claimed {
+            0
+        } else {
+            ACCESS_KEY_ALLOWANCE.as_yoctonear()
+        };
+        storage::storage_cost(storage::ACCESS_KEY_STORAGE_BYTES)
+            + allowance
+            + ecdsa::ATTACHED_DEPOSIT.as_yoctonear() * drop_key.remaining_uses as u128
+            + drop_key.record_reserve
+    }
+
+    // removes the key from its drop and credits the creator the released storage plus refund
+    pub(crate) fn unregister_key(&mut self, key: &PublicKey, refund: u128) -> bool {
+        let drop_id = match self.drop_by_key.get(key) {
+            Some(drop_key) => drop_key.drop_id,
+            None => return false,
+        };
+
+        let initial_storage = self.storage_usage();
+        let mut drop = self.drop_by_id.get(&drop_id).unwrap().to_owned();
+        let creator = drop.creator.clone();
+        self.remove_drop_key(&mut drop, key);
+        self.drop_by_id.insert(drop_id, drop);
+        self.refund_storage(&creator, initial_storage, refund);
+        true
+    }
+
+    // unregisters the pending keys of the drop among keys, reading and writing the drop and
+    // measuring storage once for the whole batch, returns the keys removed
+    pub(crate) fn unregister_pending_keys(
+        &mut self,
+        drop_id: u128,
+        keys: Vec<PublicKey>,
+    ) -> Vec<PublicKey> {
+        let initial_storage = self.storage_usage();
+        let mut drop = self.drop_by_id.get(&drop_id).unwrap().clone();
+        let mut refund = 0;
+        let mut removed = vec![];
+        // a batch is appended to the drop, removing it from the end moves no other key
+        for key in keys.into_iter().rev() {
+            match self.drop_by_key.get(&key) {
+                Some(drop_key)
+                    if drop_key.drop_id == drop_id && drop_key.status == KeyStatus::Pending => {}
+                _ => continue,
+            }
+            refund += self.key_refund(&key);
+            self.remove_drop_key(&mut drop, &key);
+            removed.push(key);
+        }
+        let creator = drop.creator.clone();
+        self.drop_by_id.insert(drop_id, drop);
+        self.refund_storage(&creator, initial_storage, refund);
+        removed
+    }
+
+    // removes active keys from their drops and deletes their access keys in one batch,
+    // refunding the reservation of their remaining uses, see key_refund
+    pub(crate) fn remove_keys(&mut self, drop_id: u128, keys: Vec<PublicKey>) {
+        if keys.is_empty() {
+            return;
+        }
+        events::Event::KeyRemoved {
+            drop_id: U128(drop_id),
+            keys: keys.clone(),
+        }
+        .emit();
+        let mut promise: Option<Promise> = None;
+        for key in keys {
+            self.unregister_key(&key, self.key_refund(&key));
+            promise = Some(
+                promise
+                    .unwrap_or_else(|| Promise::new(env::current_account_id()))
+                    .delete_key(key),
+            );
+        }
+    }
+
+    // up to limit active keys of the drop, from the last index down
+    // so removing them doesn't move the keys that remain
+    pub(crate) fn active_keys(&self, drop_id: u128, limit: usize) -> Vec<PublicKey> {
+        let key_count = self.drop_by_id.get(&drop_id).unwrap().key_count;
+        (0..key_count)
+            .rev()
+            .map(|index| self.drop_keys.get(&(drop_id, index)).unwrap())
+            .filter(|key| self.drop_by_key.get(*key).unwrap().status == KeyStatus::Active)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn drop_keys(&self, drop_id: u128, from_index: u64, limit: u64) -> Vec<PublicKey> {
+        let key_count = self.drop_by_id.get(&drop_id).unwrap().key_count;
+        (from_index..key_count.min(from_index.saturating_add(limit)))
+            .map(|index| self.drop_keys.get(&(drop_id, index)).unwrap().clone())
+            .collect()
+    }
+}
+
+#[near]
+impl Contract {
+    // a batch of AddKey actions either lands as a whole or not at all,
+    // e.g. when one of the keys already exists on the contract account
+    #[private]
+    pub fn on_keys_added(&mut self, drop_id: U128, keys: Vec<PublicKey>) -> bool {
+        let success = near_sdk::is_promise_success();
+        if !success {
+            // the access keys were never created, refund the full reservation
+            let removed = self.unregister_pending_keys(drop_id.0, keys);
+            log!("key provisioning failed for {} keys", removed.len());
+            return false;
+        }
+        let mut added = vec![];
+        for key in keys {
+            if let Some(drop_key) = self.drop_by_key.get_mut(&key) {
+                if drop_key.drop_id == drop_id.0 && drop_key.status == KeyStatus::Pending {
+                    drop_key.status = KeyStatus::Active;
+                    added.push(key);
+                }
+            }
+        }
+        if !added.is_empty() {
+            events::Event::KeyAdded {
+                drop_id,
+                keys: added,
+            }
+            .emit();
+        }
+        true
+    }
+
+    // views
+
+    // None when the key is not registered, including keys rolled back after a failed batch
+    pub fn get_key_status(&self, key: PublicKey) -> Option<KeyStatus> {
+        self.drop_by_key.get(&key).map(|drop_key| drop_key.status)
+    }
+}
+
+#[test]
+fn test_rollback_fits_callback_gas() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let keys: Vec<PublicKey> = (0..MAX_KEYS_PER_CALL as u64)
+        .map(|i| {
+            let mut data = i.to_le_bytes().to_vec();
+            data.resize(32, 0);
+            PublicKey::from_parts(near_sdk::CurveType::ED25519, data).unwrap()
+        })
+        .collect();
+    set_context("alice.testnet", NearToken::from_near(200));
+    contract.add_drop_keys(drop_id, keys.clone());
+
+    set_promise_result(&test_key(), near_sdk::PromiseResult::Failed);
+    assert!(!contract.on_keys_added(drop_id, keys));
+    assert_eq!(contract.get_drop(drop_id).unwrap().key_count, 0);
+    // host gas only, most of the callback gas is left for wasm execution
+    assert!(env::used_gas().as_gas() < KEYS_CALLBACK_GAS.as_gas() / 4);
+}
@@ -1,197 +1,1510 @@
 use hex::{decode, encode};
 use near_sdk::{
     env::{self},
-    json_types::U128,
+    json_types::{U128, U64},
     log, near, require,
     store::{IterableMap, LookupMap},
     AccountId, Allowance, Gas, NearToken, PanicOnDefault, Promise, PromiseError, PublicKey,
 };
+mod address;
 mod bitcoin_tx;
+mod claims;
 mod ecdsa;
+mod events;
 mod external;
+mod kdf;
+mod keys;
+mod migrate;
+mod network;
+mod storage;
+#[cfg(test)]
+mod test_utils;
 mod utils;
 
+#[cfg(test)]
+use network::Chain;
+use network::Network;
+#[cfg(test)]
+use test_utils::*;
+
 const CALLBACK_GAS: Gas = Gas::from_tgas(100);
+// rolling back MAX_KEYS_PER_CALL keys takes about 12 Tgas of host gas, see test_rollback_fits_callback_gas
+const KEYS_CALLBACK_GAS: Gas = Gas::from_tgas(100);
 pub const ACCESS_KEY_METHODS: &str = "claim";
 pub const ACCESS_KEY_ALLOWANCE: NearToken = NearToken::from_near(1);
+// bounds the storage writes and AddKey actions of add_drop_keys to fit in one call's gas
+pub const MAX_KEYS_PER_CALL: usize = 100;
+// page size of paginated views when no limit is given
+pub const DEFAULT_VIEW_LIMIT: u32 = 100;
 
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub struct Drop {
-    target: u8,
+    network: Network,
     amount: u128,
+    creator: AccountId,
     funder: String,
     path: String,
-    keys: Vec<String>,
+    // MPC key version the funder is derived from and claims are signed with
+    key_version: u32,
+    key_count: u64,
     op_return_script: Option<Vec<u8>>,
+    // successful claims each key can make before its access key is deleted
+    uses_per_key: u32,
+    // limits across all keys, checked against settled and in-flight claims
+    max_claims: Option<u64>,
+    total_budget: Option<u128>,
+    // successful claims
+    claims: u64,
+    spent: u128,
+    pending_claims: u64,
+    pending_amount: u128,
+    // claim window, block timestamps in nanoseconds
+    starts_at: Option<u64>,
+    expires_at: Option<u64>,
+    // claim records of the drop including failed claims, see claims
+    record_count: u64,
+    // claims send the whole utxo minus the fee, amount bounds the utxo value
+    sweep: bool,
+    max_fee: Option<u128>,
+}
+
+// Drop as returned by views, with large numbers as strings
+#[near(serializers = [json])]
+pub struct DropView {
+    pub drop_id: U128,
+    pub network: Network,
+    pub amount: U128,
+    pub creator: AccountId,
+    pub funder: String,
+    pub path: String,
+    pub key_version: u32,
+    pub key_count: u64,
+    pub op_return_hex: Option<String>,
+    pub uses_per_key: u32,
+    pub max_claims: Option<u64>,
+    pub total_budget: Option<U128>,
+    pub claims: u64,
+    pub spent: U128,
+    pub pending_claims: u64,
+    pub pending_amount: U128,
+    pub starts_at: Option<U64>,
+    pub expires_at: Option<U64>,
+    pub record_count: u64,
+    pub sweep: bool,
+    pub max_fee: Option<U128>,
+}
+
+impl Drop {
+    pub fn to_view(&self, drop_id: u128) -> DropView {
+        DropView {
+            drop_id: U128(drop_id),
+            network: self.network,
+            amount: U128(self.amount),
+            creator: self.creator.clone(),
+            funder: self.funder.clone(),
+            path: self.path.clone(),
+            key_version: self.key_version,
+            key_count: self.key_count,
+            op_return_hex: self.op_return_script.as_ref().map(encode),
+            uses_per_key: self.uses_per_key,
+            max_claims: self.max_claims,
+            total_budget: self.total_budget.map(U128),
+            claims: self.claims,
+            spent: U128(self.spent),
+            pending_claims: self.pending_claims,
+            pending_amount: U128(self.pending_amount),
+            starts_at: self.starts_at.map(U64),
+            expires_at: self.expires_at.map(U64),
+            record_count: self.record_count,
+            sweep: self.sweep,
+            max_fee: self.max_fee.map(U128),
+        }
+    }
+}
+
+// optional settings of add_drop, left out they default to no limits
+#[near(serializers = [json])]
+#[derive(Default)]
+pub struct DropOptions {
+    // rejected until the claim tx carries the OP_RETURN output
+    pub op_return_hex: Option<String>,
+    // defaults to 1
+    pub uses_per_key: Option<u32>,
+    pub max_claims: Option<u64>,
+    // in the network's smallest unit, e.g. sats
+    pub total_budget: Option<U128>,
+    pub starts_at: Option<U64>,
+    pub expires_at: Option<U64>,
+    // defaults to the signer's latest key version
+    pub key_version: Option<u32>,
+    // claims spend a whole utxo of at most amount to the receiver, without change
+    pub sweep: Option<bool>,
+    // ceiling of the fee a claimer can set
+    pub max_fee: Option<U128>,
+}
+
+// MPC derivation path, namespaced by the creator so drops from different
+// accounts can never sign for each other's funder addresses
+// account ids cannot contain '/' so prefixes never collide
+pub fn namespaced_path(creator: &AccountId, path: &str) -> String {
+    format!("{}/{}", creator, path)
+}
+
+#[near(serializers = [json])]
+pub struct AddKeysResult {
+    pub added: u32,
+    // keys already registered, either in this or another drop
+    pub skipped: Vec<PublicKey>,
 }
 
 #[near(contract_state)]
 #[derive(PanicOnDefault)]
 pub struct Contract {
     pub owner_id: AccountId,
+    // root public keys of the MPC signer by key version, drop funders are derived from them
+    pub mpc_public_keys: LookupMap<u32, PublicKey>,
+    // the signer's latest key version, the default for new drops
+    pub mpc_key_version: u32,
     pub drop_id: u128,
     pub drop_by_id: IterableMap<u128, Drop>,
-    pub drop_by_key: LookupMap<String, u128>,
+    pub drop_by_key: LookupMap<PublicKey, keys::DropKey>,
+    pub drop_keys: LookupMap<(u128, u64), PublicKey>,
+    pub balances: LookupMap<AccountId, u128>,
+    pub claim_id: u64,
+    pub claim_by_id: LookupMap<u64, claims::ClaimRecord>,
+    pub drop_claims: LookupMap<(u128, u64), u64>,
+    pub claim_by_key: LookupMap<PublicKey, u64>,
 }
 
 #[near]
 impl Contract {
     #[init]
     #[private]
-    pub fn init(owner_id: AccountId) -> Self {
+    pub fn init(owner_id: AccountId, mpc_public_key: PublicKey) -> Self {
+        kdf::public_key_to_point(&mpc_public_key);
+        let mut mpc_public_keys = LookupMap::new(b"j");
+        mpc_public_keys.insert(0, mpc_public_key);
         Self {
             owner_id,
+            mpc_public_keys,
+            mpc_key_version: 0,
             drop_id: 0,
-            drop_by_id: IterableMap::new(b"a"),
-            drop_by_key: LookupMap::new(b"b"),
+            // a and b held drops and keys before the keys moved out of Drop,
+            // k holds the keys still waiting for migrate_keys, see migrate
+            drop_by_id: IterableMap::new(b"f"),
+            drop_by_key: LookupMap::new(b"e"),
+            drop_keys: LookupMap::new(b"d"),
+            balances: LookupMap::new(b"c"),
+            claim_id: 0,
+            claim_by_id: LookupMap::new(b"g"),
+            drop_claims: LookupMap::new(b"h"),
+            claim_by_key: LookupMap::new(b"i"),
         }
     }
 
-    // owner methods
+    // creator methods
+    // attached deposits are credited to the creator balance, storage is charged from it
 
+    #[payable]
     pub fn add_drop(
         &mut self,
-        target: u8,
+        network: Network,
         amount: U128,
-        // funder is uncompressed btc public key
-        funder: String,
         path: String,
-        op_return_hex: Option<String>,
-    ) {
-        require!(env::predecessor_account_id() == self.owner_id);
+        options: Option<DropOptions>,
+    ) -> U128 {
+        let DropOptions {
+            op_return_hex,
+            uses_per_key,
+            max_claims,
+            total_budget,
+            starts_at,
+            expires_at,
+            key_version,
+            sweep,
+            max_fee,
+        } = options.unwrap_or_default();
+        require!(
+            op_return_hex.is_none(),
+            "op_return_hex is not supported yet, the claim tx doesn't carry the OP_RETURN output"
+        );
+        let uses_per_key = uses_per_key.unwrap_or(1);
+        require!(uses_per_key > 0, "uses_per_key must be at least 1");
+        require!(max_claims != Some(0), "max_claims must be at least 1");
+        network
+            .chain()
+            .params()
+            .require_above_dust(amount.0, "amount");
+        let sweep = sweep.unwrap_or(false);
+        let max_fee = max_fee.map(|max_fee| max_fee.0);
+        require!(!sweep || max_fee.is_some(), "sweep drops need a max_fee");
+        // both rely on the value the claimer gives, which only such a signature binds
+        require!(
+            !sweep && max_fee.is_none() || network.chain().sighash_commits_to_value(),
+            format!(
+                "sweep and max_fee need a sighash that commits to the utxo value, {} doesn't",
+                network.chain().params().name
+            )
+        );
+        let total_budget = total_budget.map(|budget| budget.0);
+        require!(
+            total_budget.is_none_or(|budget| budget >= amount.0),
+            "total_budget must cover at least one claim"
+        );
+        let starts_at = starts_at.map(|t| t.0);
+        let expires_at = expires_at.map(|t| t.0);
+        require!(
+            expires_at.is_none_or(|t| t > env::block_timestamp() && Some(t) > starts_at),
+            "expires_at must be in the future and after starts_at"
+        );
+        let creator = env::predecessor_account_id();
+        let initial_storage = self.storage_usage();
         self.drop_id += 1;
-        let op_return_script = if op_return_hex.is_some() {
-            Some(decode(op_return_hex.unwrap()).unwrap())
-        } else {
-            None
-        };
+        let path = namespaced_path(&creator, &path);
+        let key_version = key_version.unwrap_or(self.mpc_key_version);
+        let mpc_public_key = self
+            .mpc_public_keys
+            .get(&key_version)
+            .unwrap_or_else(|| env::panic_str("unknown key_version"));
+        // the uncompressed public key the MPC signer uses for this contract and path
+        let funder =
+            kdf::derive_child_public_key(mpc_public_key, &env::current_account_id(), &path);
         self.drop_by_id.insert(
             self.drop_id,
             Drop {
-                target,
+                network,
                 amount: amount.0,
-                funder,
                 path,
-                keys: vec![],
-                op_return_script,
+                creator: creator.clone(),
+                funder,
+                key_version,
+                key_count: 0,
+                op_return_script: None,
+                uses_per_key,
+                max_claims,
+                total_budget,
+                claims: 0,
+                spent: 0,
+                pending_claims: 0,
+                pending_amount: 0,
+                starts_at,
+                expires_at,
+                record_count: 0,
+                sweep,
+                max_fee,
             },
         );
+        self.charge_storage(&creator, initial_storage, 0);
+        let drop = self.drop_by_id.get(&self.drop_id).unwrap();
+        events::Event::DropCreated {
+            drop_id: U128(self.drop_id),
+            creator,
+            network: drop.network,
+            funder: drop.funder.clone(),
+            path: drop.path.clone(),
+            key_version: drop.key_version,
+            amount,
+            uses_per_key: drop.uses_per_key,
+            max_claims: drop.max_claims,
+            total_budget: drop.total_budget.map(U128),
+            starts_at: drop.starts_at.map(U64),
+            expires_at: drop.expires_at.map(U64),
+            sweep: drop.sweep,
+            max_fee: drop.max_fee.map(U128),
+        }
+        .emit();
+        U128(self.drop_id)
     }
 
-    pub fn add_drop_key(&mut self, drop_id: U128, key: String) {
-        require!(env::predecessor_account_id() == self.owner_id);
+    // the limits can change at any time but not below current usage
+    // while keys are outstanding amount can only go down, the links were handed out for at most
+    // the current amount and on sweep drops it bounds the utxo every link can spend
+    // funder and path can't change, keys handed out sign for the funder of the drop
+    #[payable]
+    pub fn update_drop(
+        &mut self,
+        drop_id: U128,
+        amount: Option<U128>,
+        max_claims: Option<u64>,
+        total_budget: Option<U128>,
+    ) {
+        let mut drop = self.drop_by_id.get(&drop_id.0).unwrap().clone();
+        require_creator(&drop);
+        let mut changes = vec![];
 
-        if !self.drop_by_key.insert(key.clone(), drop_id.0).is_none() {
-            return;
+        if let Some(amount) = amount {
+            drop.network
+                .chain()
+                .params()
+                .require_above_dust(amount.0, "amount");
+            require!(
+                drop.key_count == 0 || amount.0 <= drop.amount,
+                "amount can't be raised while the drop has keys"
+            );
+            changes.push(change("amount", U128(drop.amount), amount));
+            drop.amount = amount.0;
+        }
+        if let Some(max_claims) = max_claims {
+            require!(
+                max_claims > 0 && max_claims >= drop.claims + drop.pending_claims,
+                "max_claims can't be below the claims already made"
+            );
+            changes.push(change("max_claims", drop.max_claims, max_claims));
+            drop.max_claims = Some(max_claims);
         }
+        if let Some(total_budget) = total_budget {
+            require!(
+                total_budget.0 >= drop.spent + drop.pending_amount,
+                "total_budget can't be below the amount already spent"
+            );
+            changes.push(change(
+                "total_budget",
+                drop.total_budget.map(U128),
+                total_budget,
+            ));
+            drop.total_budget = Some(total_budget.0);
+        }
+        require!(
+            drop.total_budget.is_none_or(|budget| budget >= drop.amount),
+            "total_budget must cover at least one claim"
+        );
+
+        let creator = drop.creator.clone();
+        let initial_storage = self.storage_usage();
+        self.drop_by_id.insert(drop_id.0, drop);
+        // setting a limit for the first time grows the drop
+        self.charge_storage(&creator, initial_storage, 0);
+        self.refund_storage(&creator, initial_storage, 0);
+        events::Event::DropUpdated { drop_id, changes }.emit();
+    }
+
+    // each key reserves storage, the access key allowance and a signature deposit per use
+    #[payable]
+    pub fn add_drop_key(&mut self, drop_id: U128, key: PublicKey) {
+        self.add_drop_keys(drop_id, vec![key]);
+    }
 
+    // registers up to MAX_KEYS_PER_CALL keys with a single batch of AddKey actions
+    // keys stay pending until on_keys_added confirms the batch, a failed batch is rolled back
+    #[payable]
+    pub fn add_drop_keys(&mut self, drop_id: U128, keys: Vec<PublicKey>) -> AddKeysResult {
+        require!(
+            keys.len() <= MAX_KEYS_PER_CALL,
+            format!("at most {} keys per call", MAX_KEYS_PER_CALL)
+        );
         let mut drop = self.drop_by_id.get(&drop_id.0).unwrap().to_owned();
-        drop.keys.push(key.clone());
+        require_creator(&drop);
+        let creator = drop.creator.clone();
+        let key_reserve = storage::key_reserve(drop.uses_per_key);
+
+        let initial_storage = self.storage_usage();
+        let mut promise: Option<Promise> = None;
+        let mut added = 0;
+        let mut added_keys = vec![];
+        let mut skipped = vec![];
+        for pk in keys {
+            if self.drop_by_key.contains_key(&pk) {
+                skipped.push(pk);
+                continue;
+            }
+            self.insert_drop_key(drop_id.0, &mut drop, pk.clone());
+            added_keys.push(pk.clone());
+            added += 1;
+
+            promise = Some(
+                promise
+                    .unwrap_or_else(|| Promise::new(env::current_account_id()))
+                    .add_access_key_allowance(
+                        pk,
+                        Allowance::limited(ACCESS_KEY_ALLOWANCE).unwrap(),
+                        env::current_account_id(),
+                        ACCESS_KEY_METHODS.to_string(),
+                    ),
+            );
+        }
+        // the drop is written once for the whole batch
         self.drop_by_id.insert(drop_id.0, drop);
+        self.charge_storage(&creator, initial_storage, key_reserve * added as u128);
 
-        let pk: PublicKey = key.parse().unwrap();
-        Promise::new(env::current_account_id())
-            .delete_key(pk.clone())
-            .then(
-                Promise::new(env::current_account_id()).add_access_key_allowance(
-                    pk,
-                    Allowance::limited(ACCESS_KEY_ALLOWANCE).unwrap(),
-                    env::current_account_id(),
-                    ACCESS_KEY_METHODS.to_string(),
-                ),
+        if let Some(promise) = promise {
+            promise.then(
+                external::this_contract::ext(env::current_account_id())
+                    .with_static_gas(KEYS_CALLBACK_GAS)
+                    .on_keys_added(drop_id, added_keys),
             );
+        }
+
+        AddKeysResult { added, skipped }
+    }
+
+    pub fn remove_key(&mut self, key: PublicKey) {
+        let drop_key = self.drop_by_key.get(&key).unwrap();
+        require!(
+            drop_key.status == keys::KeyStatus::Active,
+            "key is not active"
+        );
+        require_creator(self.drop_by_id.get(&drop_key.drop_id).unwrap());
+        let refund = self.key_refund(&key);
+        self.remove_key_internal(key, refund);
+    }
+
+    // keys stay pending if on_keys_added never ran, once their batch has landed the creator
+    // can remove them for their full reservation, returns the number removed
+    // each access key is deleted on its own since some may never have been added
+    pub fn remove_pending_keys(&mut self, drop_id: U128, keys: Vec<PublicKey>) -> u32 {
+        require!(
+            keys.len() <= MAX_KEYS_PER_CALL,
+            format!("at most {} keys per call", MAX_KEYS_PER_CALL)
+        );
+        require_creator(self.drop_by_id.get(&drop_id.0).unwrap());
+        let removed = self.unregister_pending_keys(drop_id.0, keys);
+        for key in &removed {
+            Promise::new(env::current_account_id()).delete_key(key.clone());
+        }
+        removed.len() as u32
+    }
+
+    // anyone can clean up an expired drop, the creator is refunded storage and unused reservations
+    // removes up to limit keys that aren't mid-claim, returns the number removed
+    pub fn sweep_expired(&mut self, drop_id: U128, limit: u32) -> u32 {
+        let drop = self.drop_by_id.get(&drop_id.0).unwrap();
+        require!(
+            drop.expires_at.is_some_and(|t| env::block_timestamp() >= t),
+            "drop has not expired"
+        );
+
+        let expired = self.active_keys(drop_id.0, (limit as usize).min(MAX_KEYS_PER_CALL));
+        let removed = expired.len() as u32;
+        self.remove_keys(drop_id.0, expired);
+        removed
     }
 
-    pub fn remove_key(&mut self, key: String) {
-        require!(env::predecessor_account_id() == self.owner_id);
-        self.remove_key_internal(key);
+    // removes up to MAX_KEYS_PER_CALL keys per call, then the drop itself once no keys are left
+    // keys that are still being provisioned or mid-claim are left for a later call
+    // returns true when the drop has been deleted
+    pub fn delete_drop(&mut self, drop_id: U128) -> bool {
+        let drop = self.drop_by_id.get(&drop_id.0).unwrap();
+        require_creator(drop);
+
+        let keys = self.active_keys(drop_id.0, MAX_KEYS_PER_CALL);
+        self.remove_keys(drop_id.0, keys);
+
+        let drop = self.drop_by_id.get(&drop_id.0).unwrap();
+        if drop.key_count > 0 || drop.pending_claims > 0 {
+            return false;
+        }
+        let creator = drop.creator.clone();
+        let initial_storage = self.storage_usage();
+        self.drop_by_id.remove(&drop_id.0);
+        self.refund_storage(&creator, initial_storage, 0);
+        events::Event::DropDeleted { drop_id, creator }.emit();
+        true
     }
 
     // claim
 
-    pub fn claim(
-        &mut self,
-        txid_str: String,
-        vout: u32,
-        // receiver is bs58 address
-        receiver: String,
-        change: U128,
-    ) -> Promise {
-        let key = String::from(&env::signer_account_pk());
+    pub fn claim(&mut self, args: claims::ClaimArgs) -> Promise {
+        let claims::ClaimArgs {
+            txid_str,
+            vout,
+            receiver,
+            change,
+            value,
+            fee,
+        } = args;
+        let key = env::signer_account_pk();
 
-        let drop_id = self.drop_by_key.get(&key).unwrap();
-        let drop = self.drop_by_id.get(drop_id).unwrap();
+        let drop_key = self.drop_by_key.get_mut(&key).unwrap();
+        require!(
+            drop_key.status == keys::KeyStatus::Active,
+            "key is not active"
+        );
+        let drop_id = drop_key.drop_id;
+        let drop = self.drop_by_id.get_mut(&drop_id).unwrap();
 
         // extract drop params
         let amount = drop.amount;
-        let funder = &drop.funder;
-        let path = &drop.path;
-        let op_return_script = drop.op_return_script.clone();
+        let network = drop.network;
 
-        log!("path {:?}", drop.path);
-        log!("vout {:?}", vout);
-        log!("funder {:?}", funder);
-        log!("receiver {:?}", receiver);
-        log!("amount {:?}", amount);
-        log!("change {:?}", change.0);
-        log!("op_return_script {:?}", op_return_script);
+        // a wrong address or a tx nodes won't relay would burn the claim
+        let chain = network.chain();
+        let receiver_script_pubkey = chain
+            .script_pubkey(&receiver)
+            .unwrap_or_else(|error| env::panic_str(&format!("invalid receiver: {}", error)));
+        let value = value.map(|value| value.0);
+        let (sent, change, fee) = if drop.sweep {
+            let value = value.unwrap_or_else(|| env::panic_str("value is required by sweep drops"));
+            let fee = fee
+                .unwrap_or_else(|| env::panic_str("fee is required by sweep drops"))
+                .0;
+            // the signature commits to value, so the claimer can't sweep a larger utxo of the funder
+            require!(value <= amount, "value can't exceed the drop amount");
+            require!(fee <= value, "fee can't exceed value");
+            chain
+                .params()
+                .require_above_dust(value - fee, "value - fee");
+            (value - fee, None, Some(fee))
+        } else {
+            let change = change
+                .unwrap_or_else(|| env::panic_str("change is required"))
+                .0;
+            chain.params().require_above_dust(change, "change");
+            let fee = value.map(|value| {
+                value
+                    .checked_sub(amount + change)
+                    .unwrap_or_else(|| env::panic_str("value can't be below amount + change"))
+            });
+            (amount, Some(change), fee)
+        };
+
+        let now = env::block_timestamp();
+        require!(
+            drop.starts_at.is_none_or(|t| now >= t),
+            "drop has not started"
+        );
+        require!(drop.expires_at.is_none_or(|t| now < t), "drop has expired");
+
+        require!(
+            drop.max_claims
+                .is_none_or(|max_claims| drop.claims + drop.pending_claims < max_claims),
+            "drop has reached max_claims"
+        );
+        require!(
+            drop.total_budget
+                .is_none_or(|budget| drop.spent + drop.pending_amount + amount <= budget),
+            "drop has exhausted its total_budget"
+        );
+
+        let funder = drop.funder.clone();
+        let path = drop.path.clone();
+        let key_version = drop.key_version;
+        let op_return_script = drop.op_return_script.clone();
 
         // create bitcoin tx
         let tx = bitcoin_tx::get_tx(
             &txid_str,
             vout,
             &funder,
-            &receiver,
-            amount,
-            change.0,
+            receiver_script_pubkey,
+            sent,
+            change,
             op_return_script,
+            chain.tx_version(),
         );
 
+        // the fee is only known when the claimer gives the utxo value,
+        // max_fee is only set on networks whose signature commits to it
+        if let Some(fee) = fee {
+            let min_fee = chain.params().min_fee(bitcoin_tx::max_signed_size(&tx));
+            require!(
+                fee >= min_fee,
+                format!(
+                    "fee is below the {} min relay fee of {}",
+                    chain.params().name,
+                    min_fee
+                )
+            );
+            require!(
+                drop.max_fee.is_none_or(|max_fee| fee <= max_fee),
+                "fee exceeds the drop max_fee"
+            );
+        }
+
+        drop.pending_claims += 1;
+        drop.pending_amount += amount;
+        // one claim per key at a time, settled in the callback
+        drop_key.status = keys::KeyStatus::Claiming;
+        drop_key.claimed = true;
+
         // prepare args for Chain Signatures call ecdsa::get_sig
-        let encoded_tx = bitcoin_tx::get_encoded_tx(tx.clone());
-        let payload = bitcoin_tx::sha256d(encoded_tx);
-        let key_version = 0;
+        let payload = chain
+            .sighash(&tx, value)
+            .unwrap_or_else(|error| env::panic_str(&error));
+
+        events::Event::ClaimStarted {
+            drop_id: U128(drop_id),
+            claim_id: U64(self.claim_id + 1),
+            key: key.clone(),
+            receiver: receiver.clone(),
+            amount: U128(sent),
+        }
+        .emit();
+
+        // the claim record is paid from the key's reservation, including the tx hash set once signed
+        let initial_storage = self.storage_usage();
+        let mut drop = self.drop_by_id.get(&drop_id).unwrap().clone();
+        self.insert_claim_record(
+            &mut drop,
+            claims::ClaimRecord {
+                drop_id: U128(drop_id),
+                key: key.clone(),
+                receiver,
+                utxo_txid: txid_str,
+                utxo_vout: vout,
+                amount: U128(sent),
+                txid: None,
+                wtxid: None,
+                block_height: env::block_height(),
+                status: claims::ClaimStatus::Pending,
+                error: None,
+            },
+        );
+        self.drop_by_id.insert(drop_id, drop);
+        let storage_used = self.storage_usage() - initial_storage;
+        let cost = storage::storage_cost(storage_used + claims::CLAIM_TX_HASH_BYTES);
+        // failed claims draw from it without using up a use, a shortfall is paid from
+        // the allowance the contract keeps once the key has claimed
+        let drop_key = self.drop_by_key.get_mut(&key).unwrap();
+        drop_key.record_reserve = drop_key.record_reserve.saturating_sub(cost);
 
-        ecdsa::get_sig(payload, path.to_owned(), key_version).then(
+        ecdsa::get_sig(payload, path, key_version).then(
             external::this_contract::ext(env::current_account_id())
                 .with_static_gas(CALLBACK_GAS)
-                .callback(tx, decode(funder).unwrap()),
+                .callback(
+                    tx,
+                    decode(&funder).unwrap(),
+                    U128(amount),
+                    network,
+                    value.map(U128),
+                ),
         )
-
-        // todo delete key in callback to prevent double spend
     }
 
     // not public
 
-    fn remove_key_callback(&mut self) {
-        let key = String::from(&env::signer_account_pk());
-        self.remove_key_internal(key);
-    }
+    // a successful claim uses up one signature deposit and counts towards the drop limits,
+    // failed claims leave the key and drop as they were
+    // the key is deleted after its last use, its allowance paid for the gas of the claims
+    // result is the signed tx or why the claim failed
+    fn settle_claim(
+        &mut self,
+        result: &Result<bitcoin_tx::SignedTx, claims::ClaimError>,
+        amount: u128,
+    ) {
+        let key = env::signer_account_pk();
+        let signed = result.is_ok();
+        self.finish_claim_record(&key, result);
+        let drop_key = match self.drop_by_key.get_mut(&key) {
+            Some(drop_key) => drop_key,
+            None => return,
+        };
+        drop_key.status = keys::KeyStatus::Active;
+        if signed {
+            drop_key.remaining_uses -= 1;
+        }
+        let remaining_uses = drop_key.remaining_uses;
 
-    fn remove_key_internal(&mut self, key: String) {
-        let drop_id_option = self.drop_by_key.get(&key);
-        if drop_id_option.is_none() {
+        let drop = self.drop_by_id.get_mut(&drop_key.drop_id).unwrap();
+        drop.pending_claims -= 1;
+        drop.pending_amount -= amount;
+        if !signed {
             return;
         }
+        drop.claims += 1;
+        drop.spent += amount;
 
-        let drop_id = drop_id_option.unwrap().to_owned();
-        let mut drop = self.drop_by_id.get(&drop_id).unwrap().to_owned();
-        drop.keys.retain(|s| s != &key);
-        self.drop_by_id.insert(drop_id, drop);
+        if remaining_uses == 0 {
+            let refund = self.key_refund(&key);
+            self.remove_key_internal(key, refund);
+        }
+    }
 
-        let pk: PublicKey = key.parse().unwrap();
-        Promise::new(env::current_account_id()).delete_key(pk.clone());
+    fn remove_key_internal(&mut self, key: PublicKey, refund: u128) {
+        let drop_id = match self.drop_by_key.get(&key) {
+            Some(drop_key) => drop_key.drop_id,
+            None => return,
+        };
+        self.unregister_key(&key, refund);
+        events::Event::KeyRemoved {
+            drop_id: U128(drop_id),
+            keys: vec![key.clone()],
+        }
+        .emit();
+        Promise::new(env::current_account_id()).delete_key(key);
     }
 
     // views
 
-    pub fn get_drops(&self) -> Vec<U128> {
-        self.drop_by_id.keys().map(|k| U128(*k)).collect()
+    pub fn get_drop(&self, drop_id: U128) -> Option<DropView> {
+        self.drop_by_id
+            .get(&drop_id.0)
+            .map(|drop| drop.to_view(drop_id.0))
     }
 
-    pub fn get_keys(&self, drop_id: U128) -> Vec<String> {
-        let drop = self.drop_by_id.get(&drop_id.0).unwrap();
-        drop.keys.clone()
+    pub fn get_drops(&self, from_index: Option<u64>, limit: Option<u32>) -> Vec<DropView> {
+        self.drop_by_id
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_VIEW_LIMIT) as usize)
+            .map(|(drop_id, drop)| drop.to_view(*drop_id))
+            .collect()
+    }
+
+    pub fn get_drop_count(&self) -> u32 {
+        self.drop_by_id.len()
+    }
+
+    pub fn get_drop_for_key(&self, key: PublicKey) -> Option<DropView> {
+        self.drop_by_key
+            .get(&key)
+            .and_then(|drop_key| self.get_drop(U128(drop_key.drop_id)))
+    }
+
+    // keys are returned in their canonical "curve:base58" form
+    pub fn get_keys(
+        &self,
+        drop_id: U128,
+        from_index: Option<u64>,
+        limit: Option<u32>,
+    ) -> Vec<String> {
+        self.drop_keys(
+            drop_id.0,
+            from_index.unwrap_or(0),
+            limit.unwrap_or(DEFAULT_VIEW_LIMIT) as u64,
+        )
+        .iter()
+        .map(String::from)
+        .collect()
+    }
+}
+
+fn change(
+    field: &str,
+    old: impl near_sdk::serde::Serialize,
+    new: impl near_sdk::serde::Serialize,
+) -> events::DropChange {
+    events::DropChange {
+        field: field.to_string(),
+        old: near_sdk::serde_json::to_value(old).unwrap(),
+        new: near_sdk::serde_json::to_value(new).unwrap(),
+    }
+}
+
+fn require_creator(drop: &Drop) {
+    require!(
+        env::predecessor_account_id() == drop.creator,
+        "only the drop creator can call this method"
+    );
+}
+
+#[test]
+fn test_drop_path_namespaced_by_creator() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let drop = contract.drop_by_id.get(&drop_id.0).unwrap();
+    assert_eq!(drop.path, "alice.testnet/bitcoin-1");
+}
+
+#[test]
+#[should_panic(expected = "only the drop creator can call this method")]
+fn test_add_drop_key_other_creator() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    set_context("bob.testnet", NearToken::from_near(5));
+    contract.add_drop_key(drop_id, test_key());
+}
+
+#[test]
+fn test_key_reserve_charged_and_refunded() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let after_drop = contract.get_balance(alice.clone()).0;
+    assert!(after_drop < NearToken::from_near(5).as_yoctonear());
+
+    set_context("alice.testnet", NearToken::from_near(2));
+    contract.add_drop_key(drop_id, test_key());
+    let after_key = contract.get_balance(alice.clone()).0;
+    assert!(
+        after_key + storage::key_reserve(1) < after_drop + NearToken::from_near(2).as_yoctonear()
+    );
+
+    set_promise_result(&test_key(), near_sdk::PromiseResult::Successful(vec![]));
+    contract.on_keys_added(drop_id, vec![test_key()]);
+
+    set_context("alice.testnet", NearToken::from_near(0));
+    contract.remove_key(test_key());
+    assert_eq!(
+        contract.get_balance(alice).0,
+        after_drop + NearToken::from_near(2).as_yoctonear()
+    );
+}
+
+#[test]
+fn test_allowance_kept_after_first_claim() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    let keys = test_keys(2);
+    add_active_keys(&mut contract, drop_id, keys.clone());
+
+    // a key that never claimed gets its allowance back
+    let before = contract.get_balance(alice.clone()).0;
+    contract.remove_key(keys[0].clone());
+    let refund = contract.get_balance(alice.clone()).0 - before;
+    assert!(refund > storage::key_reserve(2));
+
+    // a failed claim also spent gas from the allowance
+    claim(&mut contract, &keys[1]);
+    callback(&mut contract, Err(PromiseError::Failed));
+    set_context("alice.testnet", NearToken::from_near(0));
+    let before = contract.get_balance(alice.clone()).0;
+    contract.remove_key(keys[1].clone());
+    let refund = contract.get_balance(alice).0 - before;
+    // less the storage of the failed claim's record
+    let reserve = storage::key_reserve(2) - ACCESS_KEY_ALLOWANCE.as_yoctonear();
+    assert!(refund < reserve);
+    assert!(refund > reserve - storage::storage_cost(storage::CLAIM_RECORD_STORAGE_BYTES));
+}
+
+#[test]
+#[should_panic(expected = "insufficient balance")]
+fn test_add_drop_key_without_deposit() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    set_context("alice.testnet", NearToken::from_near(0));
+    contract.add_drop_key(drop_id, test_key());
+}
+
+#[test]
+fn test_add_drop_keys_skips_duplicates() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let mut keys = test_keys(3);
+    keys.push(keys[0].clone());
+
+    set_context("alice.testnet", NearToken::from_near(10));
+    let result = contract.add_drop_keys(drop_id, keys.clone());
+    assert_eq!(result.added, 3);
+    assert_eq!(result.skipped, vec![keys[0].clone()]);
+
+    let result = contract.add_drop_keys(drop_id, test_keys(4));
+    assert_eq!(result.added, 1);
+    assert_eq!(result.skipped.len(), 3);
+    assert_eq!(contract.get_keys(drop_id, None, None).len(), 4);
+}
+
+#[test]
+fn test_remove_key_moves_last_key() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let keys = test_keys(3);
+
+    add_active_keys(&mut contract, drop_id, keys.clone());
+    contract.remove_key(keys[0].clone());
+    assert_eq!(
+        contract.get_keys(drop_id, None, None),
+        vec![String::from(&keys[2]), String::from(&keys[1])]
+    );
+    assert_eq!(contract.drop_by_key.get(&keys[2]).unwrap().index, 0);
+
+    contract.remove_key(keys[1].clone());
+    assert_eq!(
+        contract.get_keys(drop_id, None, None),
+        vec![String::from(&keys[2])]
+    );
+    assert_eq!(contract.drop_by_id.get(&drop_id.0).unwrap().key_count, 1);
+}
+
+#[test]
+fn test_failed_key_batch_rolled_back() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let before = contract.get_balance(alice.clone()).0;
+
+    set_context("alice.testnet", NearToken::from_near(10));
+    contract.add_drop_keys(drop_id, test_keys(2));
+    assert_eq!(
+        contract.get_key_status(test_keys(1)[0].clone()),
+        Some(keys::KeyStatus::Pending)
+    );
+
+    set_promise_result(&test_key(), near_sdk::PromiseResult::Failed);
+    assert!(!contract.on_keys_added(drop_id, test_keys(2)));
+    assert_eq!(contract.get_key_status(test_keys(1)[0].clone()), None);
+    assert!(contract.get_keys(drop_id, None, None).is_empty());
+    assert_eq!(
+        contract.get_balance(alice).0,
+        before + NearToken::from_near(10).as_yoctonear()
+    );
+}
+
+#[test]
+fn test_remove_pending_keys() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    let before = contract.get_balance(alice.clone()).0;
+
+    // the callback of this batch never runs
+    set_context("alice.testnet", NearToken::from_near(10));
+    contract.add_drop_keys(drop_id, test_keys(2));
+    set_context("bob.testnet", NearToken::from_near(0));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        contract.remove_pending_keys(drop_id, test_keys(2));
+    }));
+    assert!(result.is_err());
+
+    set_context("alice.testnet", NearToken::from_near(0));
+    let mut keys = test_keys(2);
+    keys.push(test_key());
+    // the active key is left alone
+    assert_eq!(contract.remove_pending_keys(drop_id, keys), 2);
+    assert_eq!(contract.get_key_status(test_keys(1)[0].clone()), None);
+    assert_eq!(
+        contract.get_keys(drop_id, None, None),
+        vec![String::from(&test_key())]
+    );
+    assert_eq!(
+        contract.get_balance(alice).0,
+        before + NearToken::from_near(10).as_yoctonear()
+    );
+}
+
+#[test]
+fn test_multi_use_key() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    assert_eq!(
+        contract.get_key_status(test_key()),
+        Some(keys::KeyStatus::Claiming)
+    );
+    callback(&mut contract, signature_result());
+    assert_eq!(
+        contract
+            .drop_by_key
+            .get(&test_key())
+            .unwrap()
+            .remaining_uses,
+        1
+    );
+
+    // a failed signature does not use up the key
+    claim(&mut contract, &test_key());
+    callback(&mut contract, Err(PromiseError::Failed));
+    assert_eq!(
+        contract
+            .drop_by_key
+            .get(&test_key())
+            .unwrap()
+            .remaining_uses,
+        1
+    );
+    assert_eq!(
+        contract.get_key_status(test_key()),
+        Some(keys::KeyStatus::Active)
+    );
+
+    claim(&mut contract, &test_key());
+    callback(&mut contract, signature_result());
+    assert_eq!(contract.get_key_status(test_key()), None);
+    assert!(contract.get_keys(drop_id, None, None).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "key is not active")]
+fn test_concurrent_claims() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    claim(&mut contract, &test_key());
+}
+
+#[test]
+fn test_max_claims_counts_pending_claims() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            max_claims: Some(1),
+            ..Default::default()
+        },
+    );
+    let keys = test_keys(2);
+    add_active_keys(&mut contract, drop_id, keys.clone());
+
+    claim(&mut contract, &keys[0]);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        claim(&mut contract, &keys[1]);
+    }));
+    assert!(result.is_err());
+
+    // a failed claim frees its slot
+    set_claim_context(&keys[0]);
+    callback(&mut contract, Err(PromiseError::Failed));
+    claim(&mut contract, &keys[1]);
+    callback(&mut contract, signature_result());
+    let drop = contract.drop_by_id.get(&drop_id.0).unwrap();
+    assert_eq!((drop.claims, drop.spent, drop.pending_claims), (1, 546, 0));
+}
+
+#[test]
+#[should_panic(expected = "drop has exhausted its total_budget")]
+fn test_total_budget() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            total_budget: Some(U128(1000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    claim(&mut contract, &test_key());
+    callback(&mut contract, signature_result());
+    claim(&mut contract, &test_key());
+}
+
+#[test]
+fn test_claim_window() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            starts_at: Some(U64(100)),
+            expires_at: Some(U64(200)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    for (timestamp, error) in [(99, "drop has not started"), (200, "drop has expired")] {
+        set_block_timestamp(timestamp);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            claim(&mut contract, &test_key());
+        }));
+        assert!(*result.unwrap_err().downcast::<String>().unwrap() == error);
+    }
+
+    set_block_timestamp(100);
+    claim(&mut contract, &test_key());
+    set_block_timestamp(0);
+}
+
+#[test]
+fn test_sweep_expired() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            expires_at: Some(U64(200)),
+            ..Default::default()
+        },
+    );
+    let before = contract.get_balance(alice.clone()).0;
+    add_active_keys(&mut contract, drop_id, test_keys(3));
+    claim(&mut contract, &test_keys(3)[0]);
+
+    set_block_timestamp(200);
+    set_context("carol.testnet", NearToken::from_near(0));
+    assert_eq!(contract.sweep_expired(drop_id, 10), 2);
+    // the key mid-claim is left for its callback
+    assert_eq!(
+        contract.get_keys(drop_id, None, None),
+        vec![String::from(&test_keys(3)[0])]
+    );
+    // only the key mid-claim is still reserved
+    let outstanding =
+        before + NearToken::from_near(10).as_yoctonear() - contract.get_balance(alice).0;
+    assert!(outstanding > storage::key_reserve(1));
+    assert!(outstanding < storage::key_reserve(1) + storage::storage_cost(500));
+    set_block_timestamp(0);
+}
+
+#[test]
+fn test_delete_drop() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    let keys = test_keys(3);
+    add_active_keys(&mut contract, drop_id, keys.clone());
+    let before_claim = contract.get_balance(alice.clone()).0;
+    claim(&mut contract, &keys[0]);
+    // the claim record is paid from the key's reservation
+    assert_eq!(contract.get_balance(alice.clone()).0, before_claim);
+    let record_reserve = storage::storage_cost(storage::CLAIM_RECORD_STORAGE_BYTES);
+    let claim_record = record_reserve - contract.drop_by_key.get(&keys[0]).unwrap().record_reserve;
+
+    set_context("alice.testnet", NearToken::from_near(0));
+    // the key mid-claim keeps the drop alive until its callback
+    assert!(!contract.delete_drop(drop_id));
+    assert_eq!(
+        contract.get_keys(drop_id, None, None),
+        vec![String::from(&keys[0])]
+    );
+
+    set_claim_context(&keys[0]);
+    callback(&mut contract, signature_result());
+    set_context("alice.testnet", NearToken::from_near(0));
+    assert!(contract.delete_drop(drop_id));
+    assert!(contract.get_drops(None, None).is_empty());
+    assert_eq!(contract.get_key_status(keys[1].clone()), None);
+
+    // everything but the used signature deposit, allowance, the claim record and
+    // the 74 bytes of alice's balance entry is refunded
+    let spent = storage::key_reserve(1)
+        - storage::storage_cost(storage::ACCESS_KEY_STORAGE_BYTES)
+        - record_reserve
+        + claim_record
+        + storage::storage_cost(74);
+    assert_eq!(
+        contract.get_balance(alice).0,
+        NearToken::from_near(11).as_yoctonear() - spent
+    );
+}
+
+#[test]
+#[should_panic(expected = "only the drop creator can call this method")]
+fn test_delete_drop_other_creator() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    set_context("bob.testnet", NearToken::from_near(0));
+    contract.delete_drop(drop_id);
+}
+
+#[test]
+fn test_update_drop() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            max_claims: Some(5),
+            ..Default::default()
+        },
+    );
+    contract.update_drop(drop_id, Some(U128(1000)), None, Some(U128(5000)));
+    let drop = contract.drop_by_id.get(&drop_id.0).unwrap();
+    assert_eq!(drop.amount, 1000);
+    assert_eq!(drop.total_budget, Some(5000));
+
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    claim(&mut contract, &test_key());
+    set_context("alice.testnet", NearToken::from_near(0));
+    // the claim in flight already counts against the limits
+    contract.update_drop(drop_id, Some(U128(800)), Some(1), None);
+    let drop = contract.drop_by_id.get(&drop_id.0).unwrap();
+    assert_eq!(drop.max_claims, Some(1));
+    assert_eq!(drop.amount, 800);
+}
+
+#[test]
+#[should_panic(expected = "amount can't be raised while the drop has keys")]
+fn test_update_drop_raise_amount_with_keys() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    contract.update_drop(drop_id, Some(U128(1000)), None, None);
+}
+
+#[test]
+#[should_panic(expected = "total_budget can't be below the amount already spent")]
+fn test_update_drop_budget_below_spent() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            total_budget: Some(U128(2000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    claim(&mut contract, &test_key());
+    callback(&mut contract, signature_result());
+    set_context("alice.testnet", NearToken::from_near(0));
+    contract.update_drop(drop_id, None, None, Some(U128(500)));
+}
+
+#[test]
+fn test_drop_views() {
+    let mut contract = setup("alice.testnet");
+    let first = add_test_drop(&mut contract, DropOptions::default());
+    let second = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(3),
+            ..Default::default()
+        },
+    );
+    let keys = test_keys(3);
+    add_active_keys(&mut contract, second, keys.clone());
+
+    assert_eq!(contract.get_drop_count(), 2);
+    let drop = contract.get_drop(second).unwrap();
+    assert_eq!(drop.uses_per_key, 3);
+    assert_eq!(drop.key_count, 3);
+    assert_eq!(drop.creator.as_str(), "alice.testnet");
+    assert!(contract.get_drop(U128(3)).is_none());
+
+    let drops = contract.get_drops(Some(1), Some(10));
+    assert_eq!(drops.len(), 1);
+    assert_eq!(drops[0].drop_id, second);
+    assert_eq!(contract.get_drops(None, Some(1))[0].drop_id, first);
+
+    assert_eq!(
+        contract.get_keys(second, Some(1), Some(1)),
+        vec![String::from(&keys[1])]
+    );
+    assert_eq!(contract.get_keys(second, Some(5), None).len(), 0);
+    assert_eq!(
+        contract.get_drop_for_key(keys[2].clone()).unwrap().drop_id,
+        second
+    );
+    assert!(contract.get_drop_for_key(test_key()).is_none());
+}
+
+#[test]
+#[should_panic(expected = "amount must be at least the dogecoin-testnet dust limit of 1000000")]
+fn test_add_drop_below_dust() {
+    let mut contract = setup("alice.testnet");
+    add_network_drop(
+        &mut contract,
+        Network::DogecoinTestnet,
+        546,
+        DropOptions::default(),
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "op_return_hex is not supported yet, the claim tx doesn't carry the OP_RETURN output"
+)]
+fn test_add_drop_op_return() {
+    let mut contract = setup("alice.testnet");
+    add_test_drop(
+        &mut contract,
+        DropOptions {
+            op_return_hex: Some("6a".to_string()),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "invalid receiver: mwVgE7n7nwtc3TtTDxN8c2gntFtVpBwBtK has version byte 0x6f, dogecoin-testnet addresses use 0x71 (p2pkh) or 0xc4 (p2sh)"
+)]
+fn test_claim_checks_receiver_network() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::DogecoinTestnet,
+        100_000_000,
+        DropOptions::default(),
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    set_claim_context(&test_key());
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: TEST_RECEIVER.to_string(),
+        change: Some(U128(1_000_000)),
+        value: None,
+        fee: None,
+    });
+}
+
+#[test]
+#[should_panic(expected = "change must be at least the bitcoin-testnet dust limit of 546")]
+fn test_claim_change_below_dust() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    set_claim_context(&test_key());
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: TEST_RECEIVER.to_string(),
+        change: Some(U128(0)),
+        value: None,
+        fee: None,
+    });
+}
+
+#[test]
+fn test_sweep_claim() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        10_000,
+        DropOptions {
+            sweep: Some(true),
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+
+    sweep_claim(&mut contract, &test_key(), 8_000, 500);
+    let record = contract.get_claim_by_key(test_key()).unwrap();
+    assert_eq!(record.amount, U128(7_500));
+    // the budget counts the drop amount, the most a claim can spend
+    let drop = contract.get_drop(drop_id).unwrap();
+    assert_eq!(drop.pending_amount, U128(10_000));
+    assert!(drop.sweep);
+    assert_eq!(drop.max_fee, Some(U128(1_000)));
+}
+
+#[test]
+#[should_panic(expected = "sweep drops need a max_fee")]
+fn test_sweep_drop_without_max_fee() {
+    let mut contract = setup("alice.testnet");
+    add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        10_000,
+        DropOptions {
+            sweep: Some(true),
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+#[should_panic(expected = "fee exceeds the drop max_fee")]
+fn test_sweep_claim_above_max_fee() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        10_000,
+        DropOptions {
+            sweep: Some(true),
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    sweep_claim(&mut contract, &test_key(), 10_000, 1_001);
+}
+
+#[test]
+#[should_panic(expected = "fee is below the bitcoin-cash-testnet min relay fee of 225")]
+fn test_sweep_claim_below_min_relay_fee() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        10_000,
+        DropOptions {
+            sweep: Some(true),
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    sweep_claim(&mut contract, &test_key(), 10_000, 224);
+}
+
+#[test]
+#[should_panic(expected = "value can't exceed the drop amount")]
+fn test_sweep_claim_larger_utxo() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_network_drop(
+        &mut contract,
+        Network::BitcoinCashTestnet,
+        10_000,
+        DropOptions {
+            sweep: Some(true),
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    sweep_claim(&mut contract, &test_key(), 10_001, 500);
+}
+
+#[test]
+#[should_panic(expected = "fee is below the bitcoin-testnet min relay fee of 259")]
+fn test_claim_with_value_checks_fee() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(&mut contract, DropOptions::default());
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    set_claim_context(&test_key());
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: TEST_RECEIVER.to_string(),
+        change: Some(U128(1000)),
+        value: Some(U128(546 + 1000 + 258)),
+        fee: None,
+    });
+}
+
+#[test]
+fn test_sweep_needs_value_in_sighash() {
+    let mut contract = setup("alice.testnet");
+    for options in [
+        DropOptions {
+            sweep: Some(true),
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+        DropOptions {
+            max_fee: Some(U128(1_000)),
+            ..Default::default()
+        },
+    ] {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            add_network_drop(&mut contract, Network::Litecoin, 10_000, options);
+        }));
+        assert_eq!(
+            *result.unwrap_err().downcast::<String>().unwrap(),
+            "sweep and max_fee need a sighash that commits to the utxo value, litecoin doesn't"
+        );
+    }
+}
+
+#[test]
+fn test_claim_after_creator_withdrew() {
+    let mut contract = setup("alice.testnet");
+    let drop_id = add_test_drop(
+        &mut contract,
+        DropOptions {
+            uses_per_key: Some(2),
+            ..Default::default()
+        },
+    );
+    add_active_keys(&mut contract, drop_id, vec![test_key()]);
+    let _ = contract.withdraw(None);
+    assert_eq!(contract.get_balance("alice.testnet".parse().unwrap()).0, 0);
+
+    // a failed claim and both uses are paid from the key's reservation
+    claim(&mut contract, &test_key());
+    callback(&mut contract, Err(PromiseError::Failed));
+    for _ in 0..2 {
+        claim(&mut contract, &test_key());
+        callback(&mut contract, signature_result());
     }
+    assert_eq!(contract.get_claims(drop_id, None, None).len(), 3);
+    assert_eq!(contract.get_key_status(test_key()), None);
 }
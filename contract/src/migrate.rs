@@ -0,0 +1,215 @@
+use crate::keys::KeyStatus;
+use crate::*;
+
+// state layout of deployments before drop keys moved out of the Drop struct
+#[near(serializers = [borsh])]
+pub struct OldDrop {
+    target: u8,
+    amount: u128,
+    funder: String,
+    path: String,
+    keys: Vec<String>,
+    op_return_script: Option<Vec<u8>>,
+}
+
+#[near(serializers = [borsh])]
+pub struct OldContract {
+    owner_id: AccountId,
+    drop_id: u128,
+    drop_by_id: IterableMap<u128, OldDrop>,
+    drop_by_key: LookupMap<String, u128>,
+}
+
+// keys of migrated drops waiting for migrate_keys, in their original order
+const OLD_KEYS_PREFIX: &[u8] = b"k";
+
+#[near]
+impl Contract {
+    // moves every drop into the current layout, their keys are moved by migrate_keys
+    // existing drops are assigned to the owner and keep their un-namespaced path
+    // so their funder addresses don't change
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate(mpc_public_key: PublicKey) -> Self {
+        let mut old: OldContract = env::state_read().expect("no state to migrate");
+        let mut contract = Self::init(old.owner_id.clone(), mpc_public_key);
+        contract.drop_id = old.drop_id;
+        let mut old_keys: LookupMap<u128, Vec<String>> = LookupMap::new(OLD_KEYS_PREFIX);
+
+        let old_drops: Vec<(u128, OldDrop)> = old.drop_by_id.drain().collect();
+        for (drop_id, old_drop) in old_drops {
+            let drop = Drop {
+                network: Network::from_target(old_drop.target).expect("unknown drop target"),
+                amount: old_drop.amount,
+                creator: old.owner_id.clone(),
+                funder: old_drop.funder,
+                path: old_drop.path,
+                key_version: 0,
+                key_count: 0,
+                op_return_script: old_drop.op_return_script,
+                uses_per_key: 1,
+                max_claims: None,
+                total_budget: None,
+                claims: 0,
+                spent: 0,
+                pending_claims: 0,
+                pending_amount: 0,
+                starts_at: None,
+                expires_at: None,
+                record_count: 0,
+                sweep: false,
+                max_fee: None,
+            };
+            if !old_drop.keys.is_empty() {
+                old_keys.insert(drop_id, old_drop.keys);
+            }
+            contract.drop_by_id.insert(drop_id, drop);
+        }
+
+        contract
+    }
+
+    // moves up to limit keys of a migrated drop into the current layout,
+    // returns how many are left, call until it returns 0
+    #[private]
+    pub fn migrate_keys(&mut self, drop_id: U128, limit: u32) -> u32 {
+        let mut old_keys: LookupMap<u128, Vec<String>> = LookupMap::new(OLD_KEYS_PREFIX);
+        let mut old_drop_by_key: LookupMap<String, u128> = LookupMap::new(b"b");
+        let mut keys = match old_keys.remove(&drop_id.0) {
+            Some(keys) => keys,
+            None => return 0,
+        };
+        let rest = keys.split_off(keys.len().min(limit as usize));
+
+        let mut drop = self
+            .drop_by_id
+            .get(&drop_id.0)
+            .expect("drop not found")
+            .clone();
+        for key in keys {
+            old_drop_by_key.remove(&key);
+            // keys were stored unvalidated, malformed ones never had an access key
+            match key.parse::<PublicKey>() {
+                Ok(pk) => {
+                    self.insert_drop_key(drop_id.0, &mut drop, pk.clone());
+                    // the access key was added by the old contract, which reserved nothing
+                    // for claim records
+                    let drop_key = self.drop_by_key.get_mut(&pk).unwrap();
+                    drop_key.status = KeyStatus::Active;
+                    drop_key.record_reserve = 0;
+                }
+                Err(_) => log!("skipping malformed key {}", key),
+            }
+        }
+        self.drop_by_id.insert(drop_id.0, drop);
+
+        let left = rest.len() as u32;
+        if !rest.is_empty() {
+            old_keys.insert(drop_id.0, rest);
+        }
+        left
+    }
+}
+
+#[test]
+fn test_migrate() {
+    use near_sdk::test_utils::VMContextBuilder;
+    near_sdk::testing_env!(VMContextBuilder::new()
+        .current_account_id("linkdrop.testnet".parse().unwrap())
+        .predecessor_account_id("linkdrop.testnet".parse().unwrap())
+        .build());
+
+    let keys = vec![
+        "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".to_string(),
+        "ed25519:4Da461pSxbSX8pc8L2SiQMwgHJJBYEovMVp7XgZRZLVb".to_string(),
+        "not a key".to_string(),
+    ];
+    let mut old = OldContract {
+        owner_id: "owner.testnet".parse().unwrap(),
+        drop_id: 1,
+        drop_by_id: IterableMap::new(b"a"),
+        drop_by_key: LookupMap::new(b"b"),
+    };
+    old.drop_by_id.insert(
+        1,
+        OldDrop {
+            target: 1,
+            amount: 546,
+            funder: "04".to_string(),
+            path: "bitcoin-1".to_string(),
+            keys: keys.clone(),
+            op_return_script: None,
+        },
+    );
+    for key in &keys {
+        old.drop_by_key.insert(key.clone(), 1);
+    }
+    env::state_write(&old);
+    drop(old);
+
+    let mut contract = Contract::migrate(TEST_MPC_PUBLIC_KEY.parse().unwrap());
+    let drop = contract.drop_by_id.get(&1).unwrap();
+    assert_eq!(drop.path, "bitcoin-1");
+    assert_eq!(drop.network, Network::BitcoinTestnet);
+    assert_eq!(drop.creator.as_str(), "owner.testnet");
+    assert!(contract.get_keys(U128(1), None, None).is_empty());
+
+    // the keys move over two batches
+    assert_eq!(contract.migrate_keys(U128(1), 2), 1);
+    assert_eq!(contract.get_keys(U128(1), None, None), keys[..2]);
+    let pk: PublicKey = keys[1].parse().unwrap();
+    assert_eq!(contract.drop_by_key.get(&pk).unwrap().index, 1);
+    let old_drop_by_key: LookupMap<String, u128> = LookupMap::new(b"b");
+    assert!(!old_drop_by_key.contains_key(&keys[0]));
+    assert!(old_drop_by_key.contains_key(&keys[2]));
+
+    assert_eq!(contract.migrate_keys(U128(1), 2), 0);
+    assert_eq!(contract.drop_by_id.get(&1).unwrap().key_count, 2);
+    let old_drop_by_key: LookupMap<String, u128> = LookupMap::new(b"b");
+    assert!(!old_drop_by_key.contains_key(&keys[2]));
+    assert_eq!(contract.migrate_keys(U128(1), 2), 0);
+}
+
+#[test]
+fn test_migrate_keys_batches() {
+    use near_sdk::test_utils::VMContextBuilder;
+    near_sdk::testing_env!(VMContextBuilder::new()
+        .current_account_id("linkdrop.testnet".parse().unwrap())
+        .predecessor_account_id("linkdrop.testnet".parse().unwrap())
+        .build());
+
+    let keys: Vec<String> = test_keys(250).iter().map(String::from).collect();
+    let mut old = OldContract {
+        owner_id: "owner.testnet".parse().unwrap(),
+        drop_id: 1,
+        drop_by_id: IterableMap::new(b"a"),
+        drop_by_key: LookupMap::new(b"b"),
+    };
+    old.drop_by_id.insert(
+        1,
+        OldDrop {
+            target: 0,
+            amount: 546,
+            funder: "04".to_string(),
+            path: "bitcoin-1".to_string(),
+            keys: keys.clone(),
+            op_return_script: None,
+        },
+    );
+    for key in &keys {
+        old.drop_by_key.insert(key.clone(), 1);
+    }
+    env::state_write(&old);
+    drop(old);
+
+    let mut contract = Contract::migrate(TEST_MPC_PUBLIC_KEY.parse().unwrap());
+    let mut batches = 0;
+    while contract.migrate_keys(U128(1), 100) > 0 {
+        batches += 1;
+    }
+    assert_eq!(batches, 2);
+    assert_eq!(contract.drop_by_id.get(&1).unwrap().key_count, 250);
+    assert_eq!(contract.get_keys(U128(1), None, Some(250)), keys);
+    let last: PublicKey = keys[249].parse().unwrap();
+    assert_eq!(contract.drop_by_key.get(&last).unwrap().index, 249);
+}
@@ -0,0 +1,482 @@
+use crate::*;
+use omni_transaction::bitcoin::bitcoin_transaction::BitcoinTransaction;
+use omni_transaction::bitcoin::types::{EcdsaSighashType, ScriptBuf, Version};
+
+// upper bound of a signed claim tx: one p2pkh input spent with an uncompressed key,
+// a p2pkh receiver output and a p2pkh change output
+pub const CLAIM_TX_MAX_SIZE: u128 = 259;
+// without the change output
+pub const SWEEP_TX_MAX_SIZE: u128 = CLAIM_TX_MAX_SIZE - 34;
+
+// parameters of a bitcoin style chain a drop can target
+pub struct NetworkParams {
+    pub name: &'static str,
+    // base58 version bytes
+    pub p2pkh_version: u8,
+    pub p2sh_version: u8,
+    // outputs below this are not relayed, in the chain's smallest unit
+    pub dust_limit: u128,
+    // per 1000 bytes, in the chain's smallest unit
+    pub min_relay_fee: u128,
+    pub explorer: Option<&'static str>,
+}
+
+impl NetworkParams {
+    pub fn explorer_url(&self, txid: &str) -> Option<String> {
+        self.explorer
+            .map(|explorer| format!("{}{}", explorer, txid))
+    }
+
+    // the output script paying to a base58 p2pkh or p2sh address of this network
+    pub fn base58_script_pubkey(&self, address: &str) -> Result<ScriptBuf, String> {
+        let data = base58ck::decode_check(address)
+            .map_err(|_| format!("{} is not a base58check address", address))?;
+        match data.split_first() {
+            Some((version, hash160)) if hash160.len() == 20 => {
+                if *version == self.p2pkh_version {
+                    Ok(bitcoin_tx::p2pkh_script(hash160))
+                } else if *version == self.p2sh_version {
+                    Ok(bitcoin_tx::p2sh_script(hash160))
+                } else {
+                    Err(format!(
+                        "{} has version byte {:#04x}, {} addresses use {:#04x} (p2pkh) or {:#04x} (p2sh)",
+                        address, version, self.name, self.p2pkh_version, self.p2sh_version
+                    ))
+                }
+            }
+            _ => Err(format!("{} is not a p2pkh or p2sh address", address)),
+        }
+    }
+
+    pub fn require_above_dust(&self, value: u128, name: &str) {
+        require!(
+            value >= self.dust_limit,
+            format!(
+                "{} must be at least the {} dust limit of {}",
+                name, self.name, self.dust_limit
+            )
+        );
+    }
+
+    // rounded up, the minimum fee a node relays a tx of size bytes for
+    pub fn min_fee(&self, size: u128) -> u128 {
+        (size * self.min_relay_fee).div_ceil(1000)
+    }
+}
+
+// what differs between the chains, the claim tx itself is built by bitcoin_tx
+// the funder always spends a p2pkh utxo with its uncompressed key
+pub trait Chain {
+    fn params(&self) -> &NetworkParams;
+
+    // the output script paying to a receiver address
+    fn script_pubkey(&self, address: &str) -> Result<ScriptBuf, String> {
+        self.params().base58_script_pubkey(address)
+    }
+
+    fn funder_address(&self, uncompressed_pubkey: &str) -> String {
+        bitcoin_tx::p2pkh_address_from_ucp(uncompressed_pubkey, self.params().p2pkh_version)
+    }
+
+    fn tx_version(&self) -> Version {
+        Version::One
+    }
+
+    // appended to the DER signature
+    fn sighash_type(&self) -> u8 {
+        EcdsaSighashType::All as u8
+    }
+
+    // the hash the MPC signer signs for the claim input, value is the value of the spent utxo
+    fn sighash(&self, tx: &BitcoinTransaction, _value: Option<u128>) -> Result<Vec<u8>, String> {
+        Ok(bitcoin_tx::sha256d(bitcoin_tx::get_encoded_tx(tx.clone())))
+    }
+
+    // the legacy sighash doesn't commit to the utxo value, a claimer can spend any utxo
+    // of the funder and claim a smaller value, the difference going to the miners
+    fn sighash_commits_to_value(&self) -> bool {
+        false
+    }
+}
+
+// bitcoin, and dogecoin which keeps its address and signing rules
+pub struct Bitcoin(pub NetworkParams);
+
+impl Chain for Bitcoin {
+    fn params(&self) -> &NetworkParams {
+        &self.0
+    }
+}
+
+// receivers can also be bech32 segwit addresses
+pub struct Litecoin {
+    pub params: NetworkParams,
+    pub hrp: &'static str,
+}
+
+impl Chain for Litecoin {
+    fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    fn script_pubkey(&self, address: &str) -> Result<ScriptBuf, String> {
+        match address::segwit_decode(address) {
+            Some((hrp, script)) if hrp == self.hrp => Ok(ScriptBuf::from_bytes(script)),
+            Some((hrp, _)) => Err(format!(
+                "{} has prefix {}, {} segwit addresses use {}",
+                address, hrp, self.params.name, self.hrp
+            )),
+            None => self.params.base58_script_pubkey(address),
+        }
+    }
+}
+
+// CashAddr addresses, and BIP 143 sighashes with SIGHASH_FORKID which commit to the utxo value
+pub struct BitcoinCash {
+    pub params: NetworkParams,
+    pub prefix: &'static str,
+}
+
+const SIGHASH_FORKID: u8 = 0x40;
+
+impl Chain for BitcoinCash {
+    fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    fn script_pubkey(&self, address: &str) -> Result<ScriptBuf, String> {
+        let (prefix, version, hash160) = address::cashaddr_decode(self.prefix, address)
+            .ok_or_else(|| format!("{} is not a CashAddr address", address))?;
+        if prefix != self.prefix {
+            return Err(format!(
+                "{} has prefix {}, {} addresses use {}",
+                address, prefix, self.params.name, self.prefix
+            ));
+        }
+        match version {
+            address::CASHADDR_P2PKH if hash160.len() == 20 => {
+                Ok(bitcoin_tx::p2pkh_script(&hash160))
+            }
+            address::CASHADDR_P2SH if hash160.len() == 20 => Ok(bitcoin_tx::p2sh_script(&hash160)),
+            _ => Err(format!(
+                "{} has version byte {:#04x}, {} addresses use {:#04x} (p2pkh) or {:#04x} (p2sh)",
+                address,
+                version,
+                self.params.name,
+                address::CASHADDR_P2PKH,
+                address::CASHADDR_P2SH
+            )),
+        }
+    }
+
+    fn funder_address(&self, uncompressed_pubkey: &str) -> String {
+        address::cashaddr_encode(
+            self.prefix,
+            address::CASHADDR_P2PKH,
+            &bitcoin_tx::hash160_from_ucp(uncompressed_pubkey),
+        )
+    }
+
+    // like current bitcoin cash wallets
+    fn tx_version(&self) -> Version {
+        Version::Two
+    }
+
+    fn sighash_type(&self) -> u8 {
+        EcdsaSighashType::All as u8 | SIGHASH_FORKID
+    }
+
+    fn sighash_commits_to_value(&self) -> bool {
+        true
+    }
+
+    fn sighash(&self, tx: &BitcoinTransaction, value: Option<u128>) -> Result<Vec<u8>, String> {
+        let value = value.ok_or_else(|| {
+            format!(
+                "value of the spent utxo is required on {}",
+                self.params.name
+            )
+        })?;
+        // before signing the script_sig holds the funder script, the script code of p2pkh
+        // fork id 0 in the upper bytes of the sighash type
+        Ok(bitcoin_tx::bip143_sighash(
+            tx,
+            0,
+            &tx.input[0].script_sig,
+            value as u64,
+            self.sighash_type() as u32,
+        ))
+    }
+}
+
+pub const BITCOIN: Bitcoin = Bitcoin(NetworkParams {
+    name: "bitcoin",
+    p2pkh_version: 0x00,
+    p2sh_version: 0x05,
+    dust_limit: 546,
+    min_relay_fee: 1_000,
+    explorer: Some("https://mempool.space/tx/"),
+});
+
+pub const BITCOIN_TESTNET: Bitcoin = Bitcoin(NetworkParams {
+    name: "bitcoin-testnet",
+    p2pkh_version: 0x6f,
+    p2sh_version: 0xc4,
+    explorer: Some("https://mempool.space/testnet/tx/"),
+    ..BITCOIN.0
+});
+
+// 1 DOGE = 100_000_000 koinu, dust limit 0.01 DOGE and min relay fee 0.001 DOGE/kB
+pub const DOGECOIN: Bitcoin = Bitcoin(NetworkParams {
+    name: "dogecoin",
+    p2pkh_version: 0x1e,
+    p2sh_version: 0x16,
+    dust_limit: 1_000_000,
+    min_relay_fee: 100_000,
+    explorer: Some("https://blockexplorer.one/dogecoin/mainnet/tx/"),
+});
+
+pub const DOGECOIN_TESTNET: Bitcoin = Bitcoin(NetworkParams {
+    name: "dogecoin-testnet",
+    p2pkh_version: 0x71,
+    p2sh_version: 0xc4,
+    explorer: Some("https://blockexplorer.one/dogecoin/testnet/tx/"),
+    ..DOGECOIN.0
+});
+
+// in litoshi, the dust limit of a p2pkh output at litecoin's 30 lit/byte dust relay fee
+pub const LITECOIN: Litecoin = Litecoin {
+    params: NetworkParams {
+        name: "litecoin",
+        p2pkh_version: 0x30,
+        p2sh_version: 0x32,
+        dust_limit: 5_460,
+        min_relay_fee: 10_000,
+        explorer: Some("https://litecoinspace.org/tx/"),
+    },
+    hrp: "ltc",
+};
+
+pub const LITECOIN_TESTNET: Litecoin = Litecoin {
+    params: NetworkParams {
+        name: "litecoin-testnet",
+        p2pkh_version: 0x6f,
+        p2sh_version: 0x3a,
+        explorer: Some("https://litecoinspace.org/testnet/tx/"),
+        ..LITECOIN.params
+    },
+    hrp: "tltc",
+};
+
+pub const BITCOIN_CASH: BitcoinCash = BitcoinCash {
+    params: NetworkParams {
+        name: "bitcoin-cash",
+        explorer: Some("https://blockchair.com/bitcoin-cash/transaction/"),
+        ..BITCOIN.0
+    },
+    prefix: "bitcoincash",
+};
+
+pub const BITCOIN_CASH_TESTNET: BitcoinCash = BitcoinCash {
+    params: NetworkParams {
+        name: "bitcoin-cash-testnet",
+        explorer: None,
+        ..BITCOIN_TESTNET.0
+    },
+    prefix: "bchtest",
+};
+
+// the chain a drop pays out on
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Network {
+    Bitcoin,
+    BitcoinTestnet,
+    Dogecoin,
+    DogecoinTestnet,
+    Litecoin,
+    LitecoinTestnet,
+    BitcoinCash,
+    BitcoinCashTestnet,
+}
+
+impl Network {
+    pub fn chain(self) -> &'static dyn Chain {
+        match self {
+            Network::Bitcoin => &BITCOIN,
+            Network::BitcoinTestnet => &BITCOIN_TESTNET,
+            Network::Dogecoin => &DOGECOIN,
+            Network::DogecoinTestnet => &DOGECOIN_TESTNET,
+            Network::Litecoin => &LITECOIN,
+            Network::LitecoinTestnet => &LITECOIN_TESTNET,
+            Network::BitcoinCash => &BITCOIN_CASH,
+            Network::BitcoinCashTestnet => &BITCOIN_CASH_TESTNET,
+        }
+    }
+
+    // drops of earlier deployments stored the network as a u8 target
+    pub fn from_target(target: u8) -> Option<Self> {
+        match target {
+            0 => Some(Network::Bitcoin),
+            1 => Some(Network::BitcoinTestnet),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_script_pubkey_checks_network() {
+    let script = BITCOIN_TESTNET.script_pubkey(TEST_RECEIVER).unwrap();
+    assert_eq!(
+        script.0,
+        decode("76a914af442f0d61233c9d3fdde22d36bfb6e3e441689088ac").unwrap()
+    );
+
+    // the same key on dogecoin testnet
+    let doge_address = DOGECOIN_TESTNET.funder_address(
+        "048393e4b554ced50402b2e9fcf765941fcbf3fa2b87c450873a0127dbb8cd7d214a4be00c690901a0eae20e50faf1957f30aecd9e34c7395d1f7bdb5d79123d8a",
+    );
+    assert!(doge_address.starts_with('n'));
+    assert_eq!(
+        DOGECOIN_TESTNET.script_pubkey(&doge_address).unwrap().0,
+        script.0
+    );
+    assert_eq!(
+        DOGECOIN_TESTNET.script_pubkey(TEST_RECEIVER).unwrap_err(),
+        "mwVgE7n7nwtc3TtTDxN8c2gntFtVpBwBtK has version byte 0x6f, dogecoin-testnet addresses use 0x71 (p2pkh) or 0xc4 (p2sh)"
+    );
+    assert!(BITCOIN_TESTNET.script_pubkey(&doge_address).is_err());
+    // a mainnet address on a testnet drop
+    let mainnet_address = BITCOIN.funder_address(TEST_FUNDER);
+    assert!(BITCOIN.script_pubkey(&mainnet_address).is_ok());
+    assert!(BITCOIN_TESTNET.script_pubkey(&mainnet_address).is_err());
+    assert!(DOGECOIN.script_pubkey("not an address").is_err());
+
+    // p2sh
+    let mut p2sh = vec![DOGECOIN.0.p2sh_version];
+    p2sh.extend([7u8; 20]);
+    let script = DOGECOIN
+        .script_pubkey(&base58ck::encode_check(&p2sh))
+        .unwrap();
+    assert_eq!(script.0[..2], [0xa9, 20]);
+    assert_eq!(script.0[22], 0x87);
+}
+
+#[test]
+fn test_min_fee() {
+    assert_eq!(BITCOIN.0.min_fee(CLAIM_TX_MAX_SIZE), 259);
+    assert_eq!(DOGECOIN_TESTNET.0.min_fee(CLAIM_TX_MAX_SIZE), 25_900);
+    assert_eq!(BITCOIN.0.min_fee(1), 1);
+}
+
+#[test]
+fn test_litecoin_script_pubkey() {
+    let p2pkh = decode("76a914af442f0d61233c9d3fdde22d36bfb6e3e441689088ac").unwrap();
+    let p2wpkh = decode("0014af442f0d61233c9d3fdde22d36bfb6e3e4416890").unwrap();
+    assert_eq!(
+        LITECOIN
+            .script_pubkey("ltc1q4azz7rtpyv7f607augknd0aku0jyz6ysvvdytl")
+            .unwrap()
+            .0,
+        p2wpkh
+    );
+    assert_eq!(
+        LITECOIN_TESTNET
+            .script_pubkey("TLTC1Q4AZZ7RTPYV7F607AUGKND0AKU0JYZ6YSM7WDC4")
+            .unwrap()
+            .0,
+        p2wpkh
+    );
+    // taproot is bech32m
+    let p2tr = LITECOIN
+        .script_pubkey("ltc1ppyysjzgfpyysjzgfpyysjzgfpyysjzgfpyysjzgfpyysjzgfpyysw6dzpp")
+        .unwrap();
+    assert_eq!(p2tr.0[..2], [0x51, 32]);
+    assert!(LITECOIN
+        .script_pubkey("ltc1q4azz7rtpyv7f607augknd0aku0jyz6ysvvdytm")
+        .is_err());
+    assert_eq!(
+        LITECOIN
+            .script_pubkey("tltc1q4azz7rtpyv7f607augknd0aku0jyz6ysm7wdc4")
+            .unwrap_err(),
+        "tltc1q4azz7rtpyv7f607augknd0aku0jyz6ysm7wdc4 has prefix tltc, litecoin segwit addresses use ltc"
+    );
+
+    let legacy = LITECOIN.funder_address(
+        "048393e4b554ced50402b2e9fcf765941fcbf3fa2b87c450873a0127dbb8cd7d214a4be00c690901a0eae20e50faf1957f30aecd9e34c7395d1f7bdb5d79123d8a",
+    );
+    assert!(legacy.starts_with('L'));
+    assert_eq!(LITECOIN.script_pubkey(&legacy).unwrap().0, p2pkh);
+}
+
+#[test]
+fn test_bitcoin_cash_script_pubkey() {
+    let address = BITCOIN_CASH_TESTNET.funder_address(TEST_FUNDER);
+    assert!(address.starts_with("bchtest:q"));
+    assert_eq!(
+        BITCOIN_CASH_TESTNET.script_pubkey(&address).unwrap().0,
+        bitcoin_tx::p2pkh_script_from_ucp(TEST_FUNDER).0
+    );
+    // the prefix can be left out, but must match when given
+    assert!(BITCOIN_CASH_TESTNET.script_pubkey(&address[8..]).is_ok());
+    assert_eq!(
+        BITCOIN_CASH.script_pubkey(&address).unwrap_err(),
+        format!(
+            "{} has prefix bchtest, bitcoin-cash addresses use bitcoincash",
+            address
+        )
+    );
+    // legacy addresses are ambiguous with bitcoin and not accepted
+    assert!(BITCOIN_CASH_TESTNET.script_pubkey(TEST_RECEIVER).is_err());
+}
+
+#[test]
+fn test_bitcoin_cash_sighash() {
+    let mut tx = tx();
+    tx.version = BITCOIN_CASH.tx_version();
+    assert!(BITCOIN_CASH.sighash(&tx, None).is_err());
+    let sighash = BITCOIN_CASH.sighash(&tx, Some(10_000)).unwrap();
+    // commits to the value, unlike the legacy sighash
+    assert_ne!(sighash, BITCOIN_CASH.sighash(&tx, Some(10_001)).unwrap());
+    assert_ne!(sighash, BITCOIN.sighash(&tx, Some(10_000)).unwrap());
+    assert_eq!(BITCOIN_CASH.sighash_type(), 0x41);
+}
+
+#[test]
+fn test_bip143_sighash() {
+    use omni_transaction::bitcoin::encoding::Decodable;
+    use omni_transaction::bitcoin::types::{LockTime, TxIn, TxOut};
+    // the native P2WPKH example of BIP 143, signing its second input
+    let raw = decode("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+    let mut reader = &raw[..];
+    let tx = BitcoinTransaction {
+        version: Version::decode(&mut reader).unwrap(),
+        input: Vec::<TxIn>::decode(&mut reader).unwrap(),
+        output: Vec::<TxOut>::decode(&mut reader).unwrap(),
+        lock_time: LockTime::decode(&mut reader).unwrap(),
+    };
+    let script_code = ScriptBuf::from_bytes(
+        decode("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap(),
+    );
+    assert_eq!(
+        encode(bitcoin_tx::bip143_sighash(
+            &tx,
+            1,
+            &script_code,
+            600_000_000,
+            0x01
+        )),
+        "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+    );
+    // bitcoin cash only changes the sighash type of the same preimage to SIGHASH_ALL | SIGHASH_FORKID
+    assert_eq!(
+        encode(bitcoin_tx::bip143_sighash(
+            &tx,
+            1,
+            &script_code,
+            600_000_000,
+            0x41
+        )),
+        "467f411d178762db122a6aced76370a1c8324355bf0796502bf82eeaeda86a35"
+    );
+}
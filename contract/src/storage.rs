@@ -0,0 +1,171 @@
+use crate::*;
+
+// storage used by a function call access key on the contract account
+// public key + access key record + allowance, receiver and method name
+pub const ACCESS_KEY_STORAGE_BYTES: u64 = 160;
+
+pub fn storage_cost(bytes: u64) -> u128 {
+    env::storage_byte_cost().as_yoctonear() * bytes as u128
+}
+
+// storage of one claim record with its txid and wtxid and the two indexes pointing at it,
+// for the longest receiver address and a secp256k1 key
+pub const CLAIM_RECORD_STORAGE_BYTES: u64 = 640;
+
+// NEAR reserved per key on top of contract storage: the access key itself, its gas allowance,
+// and an MPC signature deposit and a claim record per use
+pub fn key_reserve(uses: u32) -> u128 {
+    storage_cost(ACCESS_KEY_STORAGE_BYTES)
+        + ACCESS_KEY_ALLOWANCE.as_yoctonear()
+        + (ecdsa::ATTACHED_DEPOSIT.as_yoctonear() + storage_cost(CLAIM_RECORD_STORAGE_BYTES))
+            * uses as u128
+}
+
+impl Contract {
+    // collections cache writes until flushed, flush before measuring storage
+    pub(crate) fn storage_usage(&mut self) -> u64 {
+        self.drop_by_id.flush();
+        self.drop_by_key.flush();
+        self.drop_keys.flush();
+        self.claim_by_id.flush();
+        self.drop_claims.flush();
+        self.claim_by_key.flush();
+        self.balances.flush();
+        env::storage_usage()
+    }
+
+    // a new balance entry pays for its own storage, amounts that don't cover it are kept
+    pub(crate) fn credit(&mut self, account_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        if let Some(balance) = self.balances.get_mut(account_id) {
+            *balance += amount;
+            return;
+        }
+        let initial_storage = self.storage_usage();
+        self.balances.insert(account_id.clone(), 0);
+        let entry_cost = storage_cost(self.storage_usage() - initial_storage);
+        if amount > entry_cost {
+            self.balances
+                .insert(account_id.clone(), amount - entry_cost);
+        } else {
+            self.balances.remove(account_id);
+        }
+    }
+
+    pub(crate) fn charge(&mut self, account_id: &AccountId, amount: u128) {
+        let balance = self.balances.get(account_id).copied().unwrap_or(0);
+        require!(
+            balance >= amount,
+            format!(
+                "insufficient balance: {} yoctoNEAR required, {} available",
+                amount, balance
+            )
+        );
+        self.balances.insert(account_id.clone(), balance - amount);
+    }
+
+    // credit the attached deposit then charge for storage added since initial_storage
+    pub(crate) fn charge_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: u64,
+        extra: u128,
+    ) {
+        let storage_used = self.storage_usage().saturating_sub(initial_storage);
+        self.credit(account_id, env::attached_deposit().as_yoctonear());
+        self.charge(account_id, storage_cost(storage_used) + extra);
+    }
+
+    // refund storage released since initial_storage
+    pub(crate) fn refund_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: u64,
+        extra: u128,
+    ) {
+        let storage_freed = initial_storage.saturating_sub(self.storage_usage());
+        self.credit(account_id, storage_cost(storage_freed) + extra);
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn deposit(&mut self) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.credit(&account_id, env::attached_deposit().as_yoctonear());
+        require!(
+            self.balances.contains_key(&account_id),
+            "deposit doesn't cover the storage of the balance"
+        );
+        self.get_balance(account_id)
+    }
+
+    // withdraw amount, or the full balance when amount is not provided
+    // an emptied balance is removed and its storage refunded with the transfer
+    pub fn withdraw(&mut self, amount: Option<U128>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let balance = self.balances.get(&account_id).copied().unwrap_or(0);
+        let amount = amount.map(|a| a.0).unwrap_or(balance);
+        require!(amount > 0, "nothing to withdraw");
+        self.charge(&account_id, amount);
+        let mut transfer = amount;
+        if amount == balance {
+            let initial_storage = self.storage_usage();
+            self.balances.remove(&account_id);
+            transfer += storage_cost(initial_storage - self.storage_usage());
+        }
+        Promise::new(account_id).transfer(NearToken::from_yoctonear(transfer))
+    }
+
+    // views
+
+    pub fn get_balance(&self, account_id: AccountId) -> U128 {
+        U128(self.balances.get(&account_id).copied().unwrap_or(0))
+    }
+
+    // NEAR reserved per key, excluding contract storage which is charged as used
+    pub fn get_key_reserve(&self, uses_per_key: Option<u32>) -> U128 {
+        U128(key_reserve(uses_per_key.unwrap_or(1)))
+    }
+}
+
+#[test]
+fn test_balance_entry_storage() {
+    let mut contract = setup("alice.testnet");
+    let alice: AccountId = "alice.testnet".parse().unwrap();
+    let entry_cost = storage_cost(74);
+    set_context("alice.testnet", NearToken::from_near(1));
+    let initial_storage = contract.storage_usage();
+    contract.deposit();
+    // the first deposit pays for the entry
+    assert_eq!(contract.storage_usage() - initial_storage, 74);
+    assert_eq!(
+        contract.get_balance(alice.clone()).0,
+        NearToken::from_near(1).as_yoctonear() - entry_cost
+    );
+    contract.deposit();
+    assert_eq!(
+        contract.get_balance(alice.clone()).0,
+        NearToken::from_near(2).as_yoctonear() - entry_cost
+    );
+
+    set_context("alice.testnet", NearToken::from_near(0));
+    contract.withdraw(Some(U128(NearToken::from_near(1).as_yoctonear())));
+    assert!(contract.balances.contains_key(&alice));
+    // emptying the balance frees the entry
+    let before_withdraw = contract.storage_usage();
+    contract.withdraw(None);
+    assert!(!contract.balances.contains_key(&alice));
+    assert_eq!(before_withdraw - contract.storage_usage(), 74);
+}
+
+#[test]
+#[should_panic(expected = "deposit doesn't cover the storage of the balance")]
+fn test_deposit_below_entry_storage() {
+    let mut contract = setup("alice.testnet");
+    set_context("alice.testnet", NearToken::from_yoctonear(1));
+    contract.deposit();
+}
@@ -0,0 +1,225 @@
+use crate::*;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::PromiseResult;
+use omni_transaction::bitcoin::types::Version;
+
+// 7 * G, so test signatures can be made with the derived private key
+pub const TEST_MPC_PUBLIC_KEY: &str = "secp256k1:2rYZMPLvdVcuUX6y2EFB3m5F8eC25sssVG3G9dJc2QzZDd4oi3hgXXT2G1Ay9FwDL1mHm4ZcbixChmQNGC5knKkV";
+// derived for linkdrop.testnet and alice.testnet/bitcoin-1
+pub const TEST_FUNDER: &str = "0456df2c8c67cdd7d5c1dbee1e08882725dc64cbb1b453a6b77da4c82bf5cedffe3f8f16564bff0493eaf270720ec84fada7925d0b77cdec7836b7c69460a5d665";
+pub const TEST_RECEIVER: &str = "mwVgE7n7nwtc3TtTDxN8c2gntFtVpBwBtK";
+pub const TEST_TXID: &str = "2f6ae3fbd9e4b1b1ad2b24be0c8a5ef1b79e20b6ad2a55ee1c07be5d2b1c7b4a";
+
+thread_local! {
+    static BLOCK_TIMESTAMP: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+// applies to every context set after it
+pub fn set_block_timestamp(timestamp: u64) {
+    BLOCK_TIMESTAMP.with(|t| t.set(timestamp));
+}
+
+fn context(predecessor: &str) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .block_timestamp(BLOCK_TIMESTAMP.with(|t| t.get()))
+        .current_account_id("linkdrop.testnet".parse().unwrap())
+        .signer_account_id("linkdrop.testnet".parse().unwrap())
+        .predecessor_account_id(predecessor.parse().unwrap());
+    builder
+}
+
+pub fn set_context(predecessor: &str, deposit: NearToken) {
+    near_sdk::testing_env!(context(predecessor).attached_deposit(deposit).build());
+}
+
+// claims are signed by the drop key on the contract account itself
+pub fn set_claim_context(key: &PublicKey) {
+    near_sdk::testing_env!(context("linkdrop.testnet")
+        .signer_account_pk(key.clone())
+        .build());
+}
+
+// callbacks run with the contract as predecessor, the original signer and the given promise result
+pub fn set_promise_result(key: &PublicKey, result: PromiseResult) {
+    near_sdk::testing_env!(
+        context("linkdrop.testnet")
+            .signer_account_pk(key.clone())
+            .build(),
+        near_sdk::test_vm_config(),
+        near_sdk::RuntimeFeesConfig::test(),
+        Default::default(),
+        vec![result],
+    );
+}
+
+pub fn setup(predecessor: &str) -> Contract {
+    set_context(predecessor, NearToken::from_near(5));
+    Contract::init(
+        "owner.testnet".parse().unwrap(),
+        TEST_MPC_PUBLIC_KEY.parse().unwrap(),
+    )
+}
+
+// a bitcoin testnet drop of 546 sats
+pub fn add_test_drop(contract: &mut Contract, options: DropOptions) -> U128 {
+    add_network_drop(contract, Network::BitcoinTestnet, 546, options)
+}
+
+// every test drop is made by alice.testnet with the path of TEST_FUNDER
+pub fn add_network_drop(
+    contract: &mut Contract,
+    network: Network,
+    amount: u128,
+    options: DropOptions,
+) -> U128 {
+    set_context("alice.testnet", NearToken::from_near(1));
+    contract.add_drop(
+        network,
+        U128(amount),
+        "bitcoin-1".to_string(),
+        Some(options),
+    )
+}
+
+pub fn add_active_keys(contract: &mut Contract, drop_id: U128, keys: Vec<PublicKey>) {
+    set_context("alice.testnet", NearToken::from_near(10));
+    contract.add_drop_keys(drop_id, keys.clone());
+    set_promise_result(&keys[0], PromiseResult::Successful(vec![]));
+    contract.on_keys_added(drop_id, keys);
+    set_context("alice.testnet", NearToken::from_near(0));
+}
+
+pub fn test_key() -> PublicKey {
+    "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp"
+        .parse()
+        .unwrap()
+}
+
+pub fn test_keys(n: u8) -> Vec<PublicKey> {
+    (0..n)
+        .map(|i| PublicKey::from_parts(near_sdk::CurveType::ED25519, vec![i; 32]).unwrap())
+        .collect()
+}
+
+// sweep drops are on bitcoin cash testnet, paying to the cash address of TEST_FUNDER
+pub fn sweep_claim(contract: &mut Contract, key: &PublicKey, value: u128, fee: u128) {
+    set_claim_context(key);
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: network::BITCOIN_CASH_TESTNET.funder_address(TEST_FUNDER),
+        change: None,
+        value: Some(U128(value)),
+        fee: Some(U128(fee)),
+    });
+}
+
+pub fn claim(contract: &mut Contract, key: &PublicKey) {
+    set_claim_context(key);
+    let _ = contract.claim(claims::ClaimArgs {
+        txid_str: TEST_TXID.to_string(),
+        vout: 0,
+        receiver: TEST_RECEIVER.to_string(),
+        change: Some(U128(1000)),
+        value: None,
+        fee: None,
+    });
+}
+
+fn signature_response(big_r: &str, s: &str, recovery_id: u8) -> external::SignatureResponse {
+    near_sdk::serde_json::from_value(near_sdk::serde_json::json!({
+        "big_r": { "affine_point": big_r },
+        "s": { "scalar": s },
+        "recovery_id": recovery_id
+    }))
+    .unwrap()
+}
+
+// signature of tx() by the private key of TEST_FUNDER
+pub fn signature_result() -> Result<external::SignatureResponse, PromiseError> {
+    Ok(signature_response(
+        "039D1ABAEC9F5715A15C7628244170951E0F85E87F68CA5393D3F9FC3FA23A69C8",
+        "7916BDFEE8052137DFF82EEE1E3ADFF1CCE0E3862DD941F9040906CC1C98808F",
+        1,
+    ))
+}
+
+// the same signature with s = n - s, as the MPC signer may return
+pub fn high_s_signature_result() -> Result<external::SignatureResponse, PromiseError> {
+    Ok(signature_response(
+        "034A5169F673AA632F538AAA128B6348536DB2B637FD89073D49B6A23879CDB3AD",
+        "8DE182B80B2588450FF3C78A33D35579FE9E85868BB161B45A8F793FE3E38D7D",
+        1,
+    ))
+}
+
+// signature of the bitcoin cash testnet claim tx of test_callback_bitcoin_cash
+pub fn bitcoin_cash_signature_result() -> Result<external::SignatureResponse, PromiseError> {
+    Ok(signature_response(
+        "034A5169F673AA632F538AAA128B6348536DB2B637FD89073D49B6A23879CDB3AD",
+        "36D80FAA8B761D95C2CA20164CB7B75FF7909DF84BB7B7F2581F8C0877192013",
+        1,
+    ))
+}
+
+// the omni-transaction test vector, it doesn't recover to TEST_FUNDER
+pub fn foreign_signature_result() -> Result<external::SignatureResponse, PromiseError> {
+    Ok(signature_response(
+        "03B96BFA3DA6BB4BB74EEEE9C20970725C5782F07724CD1BEFBD265C5AD5C63948",
+        "49283B618968DEFB0E660EA703D193BC1D213F5DD811A2D13307FCA01E20C5C0",
+        0,
+    ))
+}
+
+pub fn callback(
+    contract: &mut Contract,
+    result: Result<external::SignatureResponse, PromiseError>,
+) -> claims::ClaimResponse {
+    contract.callback(
+        result,
+        tx(),
+        decode(TEST_FUNDER).unwrap(),
+        U128(546),
+        Network::BitcoinTestnet,
+        None,
+    )
+}
+
+pub fn tx() -> omni_transaction::bitcoin::bitcoin_transaction::BitcoinTransaction {
+    let receiver = network::BITCOIN_TESTNET
+        .script_pubkey(TEST_RECEIVER)
+        .unwrap();
+    bitcoin_tx::get_tx(
+        TEST_TXID,
+        0,
+        TEST_FUNDER,
+        receiver,
+        546,
+        Some(1000),
+        None,
+        Version::One,
+    )
+}
+
+// BIP 66 IsValidSignatureEncoding, including the sighash byte
+pub fn is_strict_der(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    let integer_ok = |start: usize, len: usize| {
+        sig[start - 2] == 0x02
+            && len != 0
+            && sig[start] & 0x80 == 0
+            && !(len > 1 && sig[start] == 0 && sig[start + 1] & 0x80 == 0)
+    };
+    integer_ok(4, len_r) && integer_ok(6 + len_r, len_s)
+}
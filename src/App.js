@@ -85,10 +85,12 @@ const AppComp = ({ state, update }) => {
                                 methodName: 'claim',
                                 contractId,
                                 args: {
-                                    txid_str: funderTxId,
-                                    vout: 0,
-                                    receiver: funderAddress,
-                                    change: dropChange.toString(),
+                                    args: {
+                                        txid_str: funderTxId,
+                                        vout: 0,
+                                        receiver: funderAddress,
+                                        change: dropChange.toString(),
+                                    },
                                 },
                             });
                         }}
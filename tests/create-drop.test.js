@@ -3,6 +3,7 @@ import fs from 'fs';
 import * as dotenv from 'dotenv';
 dotenv.config();
 const {
+    accountId,
     REACT_APP_contractId: contractId,
     REACT_APP_MPC_PUBLIC_KEY: MPC_PUBLIC_KEY,
     REACT_APP_MPC_PATH: MPC_PATH,
@@ -31,7 +32,8 @@ test(`funder public key with path: ${MPC_PATH}`, async (t) => {
     const { address, publicKey } = await generateAddress({
         publicKey: MPC_PUBLIC_KEY,
         accountId: contractId,
-        path: MPC_PATH,
+        // drop paths are namespaced by the creator account
+        path: `${accountId}/${MPC_PATH}`,
         chain: 'bitcoin',
     });
     console.log('\n\n');
@@ -76,11 +78,12 @@ test('add drop', async (t) => {
         contractId,
         methodName: 'add_drop',
         args: {
-            target: 1,
+            network: 'BitcoinTestnet',
             amount: DROP_SATS.toString(), // sats
-            funder: funderPublicKey,
             path: MPC_PATH,
         },
+        // covers drop storage, unused balance can be withdrawn
+        attachedDeposit: BigInt(nearAPI.utils.format.parseNearAmount('0.1')),
     });
 
     t.pass();
@@ -94,6 +97,8 @@ test('add drop key', async (t) => {
             drop_id: '1',
             key: dropKeyPair.getPublicKey().toString(),
         },
+        // covers key storage, access key allowance and signature deposit
+        attachedDeposit: BigInt(nearAPI.utils.format.parseNearAmount('2')),
     });
 
     t.pass();
@@ -73,6 +73,7 @@ test('init contract', async (t) => {
         methodName: 'init',
         args: {
             owner_id: accountId,
+            mpc_public_key: MPC_PUBLIC_KEY,
         },
     });
 
@@ -73,6 +73,7 @@ test('init contract', async (t) => {
         methodName: 'init',
         args: {
             owner_id: accountId,
+            mpc_public_key: MPC_PUBLIC_KEY,
         },
     });
 
@@ -85,7 +86,8 @@ test(`funder public key with path: ${MPC_PATH}`, async (t) => {
     const { address, publicKey } = await generateAddress({
         publicKey: MPC_PUBLIC_KEY,
         accountId: contractId,
-        path: MPC_PATH,
+        // drop paths are namespaced by the creator account
+        path: `${accountId}/${MPC_PATH}`,
         chain: 'bitcoin',
     });
     console.log('\n\n');
@@ -130,11 +132,12 @@ test('add drop', async (t) => {
         contractId,
         methodName: 'add_drop',
         args: {
-            target: 1,
+            network: 'BitcoinTestnet',
             amount: DROP_SATS.toString(), // sats
-            funder: funderPublicKey,
             path: MPC_PATH,
         },
+        // covers drop storage, unused balance can be withdrawn
+        attachedDeposit: BigInt(nearAPI.utils.format.parseNearAmount('0.1')),
     });
 
     t.pass();
@@ -148,6 +151,8 @@ test('add drop key', async (t) => {
             drop_id: '1',
             key: dropKeyPair.getPublicKey().toString(),
         },
+        // covers key storage, access key allowance and signature deposit
+        attachedDeposit: BigInt(nearAPI.utils.format.parseNearAmount('2')),
     });
 
     t.pass();
@@ -195,19 +200,23 @@ test('claim drop', async (t) => {
         contractId,
         methodName: 'claim',
         args: {
-            txid_str: funderTxId,
-            vout: 0,
-            receiver: funderAddress,
-            change: dropChange.toString(),
+            args: {
+                txid_str: funderTxId,
+                vout: 0,
+                receiver: funderAddress,
+                change: dropChange.toString(),
+            },
         },
     });
 
-    console.log('\n\nraw signed transaction:\n\n', res);
+    console.log('\n\nclaim response:\n\n', res);
     console.log('\n\n');
     console.log('!!! NOT BROADCAST !!! \n\n');
     console.log('\n\n');
 
-    // broadcast(res);
+    // broadcast(res.signed_tx);
+
+    t.is(res.status, 'Signed');
 
     t.pass();
 });
@@ -58,6 +58,7 @@ export const contractCall = async ({
     contractId,
     methodName,
     args,
+    attachedDeposit = BigInt(0),
 }) => {
     const account = getAccount(accountId);
     let res;
@@ -67,6 +68,7 @@ export const contractCall = async ({
             methodName,
             args,
             gas,
+            attachedDeposit,
         });
     } catch (e) {
         console.log(e);